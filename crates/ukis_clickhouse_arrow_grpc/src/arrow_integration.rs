@@ -140,26 +140,28 @@ impl TryInto<DataFrame> for super::api::Result {
             //    &schema_field.data_type,
             //    output_column.r#type.as_str()
             //);
-            let (new_field, cast_to_perform) =
-                match (output_column.r#type.as_str(), &schema_field.data_type) {
-                    ("String", DataType::Binary) | ("FixedString", DataType::Binary) => {
-                        simple_cast(schema_field, DataType::LargeUtf8)
-                    }
-                    ("Bool", DataType::UInt8) => simple_cast(schema_field, DataType::Boolean),
-                    ("Date", DataType::UInt16) => {
-                        let mut new_field = (*schema_field).clone();
-                        let cast_to_perform = ClickhouseArrowCast::DateTimeFromChDate;
-                        new_field.data_type = cast_to_perform.output_datatype().clone();
-                        (new_field, Some(cast_to_perform))
-                    }
-                    ("DateTime", DataType::UInt32) => {
-                        let mut new_field = (*schema_field).clone();
-                        let cast_to_perform = ClickhouseArrowCast::DateTimeFromChDateTime;
-                        new_field.data_type = cast_to_perform.output_datatype().clone();
-                        (new_field, Some(cast_to_perform))
-                    }
-                    _ => ((*schema_field).clone(), None),
-                };
+            let (new_field, cast_to_perform) = match (
+                strip_type_parameters(&output_column.r#type),
+                &schema_field.data_type,
+            ) {
+                ("String", DataType::Binary) | ("FixedString", DataType::Binary) => {
+                    simple_cast(schema_field, DataType::LargeUtf8)
+                }
+                ("Bool", DataType::UInt8) => simple_cast(schema_field, DataType::Boolean),
+                ("Date", DataType::UInt16) => {
+                    let mut new_field = (*schema_field).clone();
+                    let cast_to_perform = ClickhouseArrowCast::DateTimeFromChDate;
+                    new_field.data_type = cast_to_perform.output_datatype().clone();
+                    (new_field, Some(cast_to_perform))
+                }
+                ("DateTime", DataType::UInt32) => {
+                    let mut new_field = (*schema_field).clone();
+                    let cast_to_perform = ClickhouseArrowCast::DateTimeFromChDateTime;
+                    new_field.data_type = cast_to_perform.output_datatype().clone();
+                    (new_field, Some(cast_to_perform))
+                }
+                _ => ((*schema_field).clone(), None),
+            };
             fields.push(new_field);
             casts_to_perform.push(cast_to_perform);
         }
@@ -191,6 +193,15 @@ impl TryInto<DataFrame> for super::api::Result {
     }
 }
 
+/// strip the parameters from clickhouse type names like `DateTime('UTC')`, `DateTime64(3)`
+/// or `FixedString(16)`.
+fn strip_type_parameters(type_name: &str) -> &str {
+    type_name
+        .split_once('(')
+        .map_or(type_name, |(base_type_name, _)| base_type_name)
+        .trim()
+}
+
 fn simple_cast(schema_field: &Field, data_type: DataType) -> (Field, Option<ClickhouseArrowCast>) {
     debug!(
         "Casting field {} from {:?} to {:?}",
@@ -233,3 +244,66 @@ pub fn serialize_for_clickhouse(df: &mut DataFrame) -> Result<Vec<u8>, Error> {
     ipc_writer.finish()?;
     Ok(out_buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use arrow2::array::PrimitiveArray;
+    use arrow2::chunk::Chunk;
+    use arrow2::datatypes::{DataType, Field, Schema, TimeUnit};
+    use arrow2::io::ipc::write::FileWriter;
+    use polars_core::prelude::DataFrame;
+
+    use crate::api;
+
+    use super::strip_type_parameters;
+
+    #[test]
+    fn strip_parameters_from_type_names() {
+        assert_eq!(strip_type_parameters("DateTime"), "DateTime");
+        assert_eq!(
+            strip_type_parameters("DateTime('Europe/Berlin')"),
+            "DateTime"
+        );
+        assert_eq!(strip_type_parameters("DateTime64(3)"), "DateTime64");
+        assert_eq!(strip_type_parameters("DateTime64(3, 'UTC')"), "DateTime64");
+        assert_eq!(strip_type_parameters("FixedString(16)"), "FixedString");
+    }
+
+    #[test]
+    fn datetime64_keeps_the_time_unit() {
+        // clickhouse emits DateTime64(3) columns as arrow timestamps in milliseconds
+        let data_type = DataType::Timestamp(TimeUnit::Millisecond, None);
+        let values = vec![1_651_000_000_123_i64, 1_651_000_000_456];
+        let mut output = vec![];
+        {
+            let schema = Schema::from(vec![Field::new("observed_at", data_type.clone(), false)]);
+            let mut writer =
+                FileWriter::try_new(&mut output, schema, None, Default::default()).unwrap();
+            let array = PrimitiveArray::<i64>::from_vec(values.clone()).to(data_type.clone());
+            writer
+                .write(&Chunk::new(vec![array.boxed()]), None)
+                .unwrap();
+            writer.finish().unwrap();
+        }
+        let result = api::Result {
+            output_columns: vec![api::NameAndType {
+                name: "observed_at".to_string(),
+                r#type: "DateTime64(3)".to_string(),
+            }],
+            output,
+            ..Default::default()
+        };
+
+        let df: DataFrame = result.try_into().unwrap();
+        assert_eq!(df.schema().to_arrow().fields[0].data_type, data_type);
+        let column = df.column("observed_at").unwrap().to_physical_repr();
+        assert_eq!(
+            column
+                .i64()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            values
+        );
+    }
+}
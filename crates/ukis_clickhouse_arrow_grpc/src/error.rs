@@ -12,8 +12,8 @@ pub enum Error {
     #[error(transparent)]
     TonicStatus(#[from] tonic::Status),
 
-    #[error("ClickhouseException({})", .0.to_string())]
-    ClickhouseException(ClickhouseException),
+    #[error("clickhouse exception")]
+    ClickhouseException(#[source] ClickhouseException),
 
     #[error("mismatch of arrays in chunk to number of casts")]
     CastArrayLengthMismatch,
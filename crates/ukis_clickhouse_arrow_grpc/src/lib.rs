@@ -1,4 +1,6 @@
+use std::fmt;
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
 use async_trait::async_trait;
 use polars_core::frame::DataFrame;
@@ -19,9 +21,40 @@ pub mod api;
 mod arrow_integration;
 mod error;
 pub mod export;
+#[cfg(feature = "test-helpers")]
+pub mod mock;
 
 pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 100 * 1024 * 1024;
 
+/// Configuration of the connection established by [`Client::connect_with_config`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// maximum size of the messages sent to and received from the server
+    pub max_message_size: usize,
+
+    /// timeout for establishing the connection. `None` waits until the OS gives up.
+    pub connect_timeout: Option<Duration>,
+
+    /// timeout of each request, including the execution of the query on the server.
+    /// `None` waits indefinitely.
+    pub request_timeout: Option<Duration>,
+
+    /// maximum number of concurrent requests sent over the connection.
+    /// `None` does not limit the requests.
+    pub concurrency_limit: Option<usize>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            connect_timeout: None,
+            request_timeout: None,
+            concurrency_limit: None,
+        }
+    }
+}
+
 /// Client.
 ///
 /// Pre-configures the underlying gprc service to use transport compression
@@ -34,7 +67,7 @@ impl Client {
         D: TryInto<tonic::transport::Endpoint>,
         D::Error: Into<tonic::codegen::StdError>,
     {
-        Self::connect_with_max_message_size(dst, DEFAULT_MAX_MESSAGE_SIZE).await
+        Self::connect_with_config(dst, ClientConfig::default()).await
     }
 
     pub async fn connect_with_max_message_size<D>(
@@ -45,10 +78,38 @@ impl Client {
         D: TryInto<tonic::transport::Endpoint>,
         D::Error: Into<tonic::codegen::StdError>,
     {
-        let channel = tonic::transport::Endpoint::new(dst)?.connect().await?;
+        Self::connect_with_config(
+            dst,
+            ClientConfig {
+                max_message_size,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn connect_with_config<D>(
+        dst: D,
+        config: ClientConfig,
+    ) -> Result<Self, tonic::transport::Error>
+    where
+        D: TryInto<tonic::transport::Endpoint>,
+        D::Error: Into<tonic::codegen::StdError>,
+    {
+        let mut endpoint = tonic::transport::Endpoint::new(dst)?;
+        if let Some(connect_timeout) = config.connect_timeout {
+            endpoint = endpoint.connect_timeout(connect_timeout);
+        }
+        if let Some(request_timeout) = config.request_timeout {
+            endpoint = endpoint.timeout(request_timeout);
+        }
+        if let Some(concurrency_limit) = config.concurrency_limit {
+            endpoint = endpoint.concurrency_limit(concurrency_limit);
+        }
+        let channel = endpoint.connect().await?;
         let cc = ClickHouseClient::new(channel)
-            .max_decoding_message_size(max_message_size)
-            .max_encoding_message_size(max_message_size);
+            .max_decoding_message_size(config.max_message_size)
+            .max_encoding_message_size(config.max_message_size);
         Ok(cc.into())
     }
 
@@ -165,8 +226,10 @@ pub struct ClickhouseException {
     pub stack_trace: String,
 }
 
-impl ToString for ClickhouseException {
-    fn to_string(&self) -> String {
-        format!("{}: {}", self.name, self.display_text)
+impl fmt::Display for ClickhouseException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.display_text)
     }
 }
+
+impl std::error::Error for ClickhouseException {}
@@ -0,0 +1,245 @@
+//!
+//! In-memory implementation of the [`ArrowInterface`] to unit-test code built on top of it
+//! without requiring a running ClickHouse server.
+//!
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use polars_core::frame::DataFrame;
+
+use crate::{ArrowInterface, ClickhouseException, Error, QueryInfo, QueryResult};
+
+/// a call received by the [`MockClient`]
+#[derive(Clone, Debug)]
+pub enum RecordedCall {
+    Query {
+        database: String,
+        query: String,
+    },
+    Insert {
+        database: String,
+        table: String,
+        df: DataFrame,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum MockResponse {
+    DataFrame(DataFrame),
+    Exception { name: String, display_text: String },
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    calls: Vec<RecordedCall>,
+    responses: Vec<(String, MockResponse)>,
+}
+
+/// Mock client recording all calls it receives and responding with pre-configured
+/// responses.
+///
+/// Clones share the recorded calls as well as the configured responses.
+#[derive(Clone, Debug, Default)]
+pub struct MockClient {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// respond to all queries containing `query_fragment` with the given dataframe.
+    ///
+    /// Responses are matched in the order they have been registered. Queries
+    /// without any matching response receive an empty dataframe.
+    pub fn respond_with_dataframe<S>(&self, query_fragment: S, df: DataFrame) -> &Self
+    where
+        S: AsRef<str>,
+    {
+        self.lock().responses.push((
+            query_fragment.as_ref().to_string(),
+            MockResponse::DataFrame(df),
+        ));
+        self
+    }
+
+    /// fail all queries containing `query_fragment` with an `Error::ClickhouseException`.
+    ///
+    /// Inserts are matched using the query string `insert into <table>`.
+    pub fn respond_with_exception<S1, S2>(&self, query_fragment: S1, display_text: S2) -> &Self
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        self.lock().responses.push((
+            query_fragment.as_ref().to_string(),
+            MockResponse::Exception {
+                name: "DB::Exception".to_string(),
+                display_text: display_text.as_ref().to_string(),
+            },
+        ));
+        self
+    }
+
+    /// all calls received so far
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.lock().calls.clone()
+    }
+
+    /// the query strings of all queries received so far. Inserts are not included.
+    pub fn queries(&self) -> Vec<String> {
+        self.lock()
+            .calls
+            .iter()
+            .filter_map(|call| match call {
+                RecordedCall::Query { query, .. } => Some(query.clone()),
+                RecordedCall::Insert { .. } => None,
+            })
+            .collect()
+    }
+
+    /// the dataframes of all inserts into `table` received so far
+    pub fn inserted_dataframes<S>(&self, table: S) -> Vec<DataFrame>
+    where
+        S: AsRef<str>,
+    {
+        self.lock()
+            .calls
+            .iter()
+            .filter_map(|call| match call {
+                RecordedCall::Insert {
+                    table: insert_table,
+                    df,
+                    ..
+                } if insert_table == table.as_ref() => Some(df.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("mock client state is poisoned")
+    }
+
+    fn record(&self, call: RecordedCall, query: &str) -> Result<Option<DataFrame>, Error> {
+        let mut state = self.lock();
+        state.calls.push(call);
+        let response = state
+            .responses
+            .iter()
+            .find(|(query_fragment, _)| query.contains(query_fragment.as_str()))
+            .map(|(_, response)| response.clone());
+
+        match response {
+            Some(MockResponse::DataFrame(df)) => Ok(Some(df)),
+            Some(MockResponse::Exception { name, display_text }) => {
+                Err(Error::ClickhouseException(ClickhouseException {
+                    name,
+                    display_text,
+                    stack_trace: String::new(),
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ArrowInterface for MockClient {
+    async fn execute_query_checked(&mut self, q: QueryInfo) -> Result<QueryResult, Error> {
+        self.record(
+            RecordedCall::Query {
+                database: q.database.clone(),
+                query: q.query.clone(),
+            },
+            &q.query,
+        )?;
+        Ok(QueryResult::default())
+    }
+
+    async fn execute_into_dataframe(&mut self, q: QueryInfo) -> Result<DataFrame, Error> {
+        Ok(self
+            .record(
+                RecordedCall::Query {
+                    database: q.database.clone(),
+                    query: q.query.clone(),
+                },
+                &q.query,
+            )?
+            .unwrap_or_default())
+    }
+
+    async fn insert_dataframe<S1, S2>(
+        &mut self,
+        database_name: S1,
+        table_name: S2,
+        df: DataFrame,
+    ) -> Result<(), Error>
+    where
+        S1: AsRef<str> + Send,
+        S2: AsRef<str> + Send,
+    {
+        let query = format!("insert into {}", table_name.as_ref());
+        self.record(
+            RecordedCall::Insert {
+                database: database_name.as_ref().to_string(),
+                table: table_name.as_ref().to_string(),
+                df,
+            },
+            &query,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars_core::prelude::{NamedFrom, Series};
+
+    use super::MockClient;
+    use crate::{ArrowInterface, Error, QueryInfo};
+
+    #[tokio::test]
+    async fn mock_client_responses() {
+        let mut client = MockClient::new();
+        client
+            .respond_with_dataframe(
+                "from system.columns",
+                polars_core::frame::DataFrame::new(vec![Series::new("table", &["water_05_base"])])
+                    .unwrap(),
+            )
+            .respond_with_exception("drop table", "not allowed");
+
+        let df = client
+            .execute_into_dataframe(QueryInfo {
+                query: "select table from system.columns".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(df.shape(), (1, 1));
+
+        let df = client
+            .clone()
+            .execute_into_dataframe(QueryInfo {
+                query: "select 1".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(df.shape(), (0, 0));
+
+        let res = client
+            .execute_query_checked(QueryInfo {
+                query: "drop table water_05_base".to_string(),
+                ..Default::default()
+            })
+            .await;
+        assert!(matches!(res, Err(Error::ClickhouseException(_))));
+
+        // clones share the recorded calls
+        assert_eq!(client.queries().len(), 3);
+    }
+}
@@ -18,8 +18,10 @@ use ukis_h3cellstore::export::ukis_clickhouse_arrow_grpc::{ArrowInterface, Clien
 const MAX_H3_RES: u8 = 5;
 
 fn okavango_delta_schema() -> anyhow::Result<CompactedTableSchema> {
+    // the base tables are named like `okavango_delta_05_base`. Calling `.no_base_suffix()` on
+    // the builder would name them like `okavango_delta_05` instead.
     let schema = CompactedTableSchemaBuilder::new("okavango_delta")
-        .h3_base_resolutions((0..=MAX_H3_RES).collect())
+        .h3_resolution_range(0, MAX_H3_RES)
         .temporal_partitioning(TemporalPartitioning::Months(1))
         .add_column(
             "elephant_count",
@@ -87,7 +89,6 @@ async fn main() -> anyhow::Result<()> {
 
     let schema = okavango_delta_schema()?;
     client.drop_tableset(play_db, &schema.name).await?;
-    //return Ok(());
     client.create_tableset(play_db, &schema).await?;
 
     let tablesets = client.list_tablesets(play_db).await?;
@@ -123,7 +124,9 @@ async fn main() -> anyhow::Result<()> {
         .await?;
     assert_eq!(queried_df.dataframe().shape().0, 7);
 
-    client.drop_tableset(play_db, "okavango_delta").await?;
+    let num_dropped = client.drop_tableset(play_db, "okavango_delta").await?;
+    // all base resolutions and the compacted resolutions
+    assert_eq!(num_dropped, 2 * (MAX_H3_RES as usize + 1));
     assert!(!client
         .list_tablesets(play_db)
         .await?
@@ -109,3 +109,24 @@ where
         Ok(df.shape().0 != 0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use ukis_clickhouse_arrow_grpc::mock::MockClient;
+
+    use crate::clickhouse::H3CellStore;
+
+    #[tokio::test]
+    async fn database_exists() {
+        let mut client = MockClient::new();
+        client.respond_with_dataframe(
+            "where name = 'existing'",
+            DataFrame::new(vec![Series::new("name", &["existing"])]).unwrap(),
+        );
+
+        assert!(client.database_exists("existing").await.unwrap());
+        assert!(!client.database_exists("missing").await.unwrap());
+        assert_eq!(client.queries().len(), 2);
+    }
+}
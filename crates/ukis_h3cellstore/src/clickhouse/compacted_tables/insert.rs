@@ -1,11 +1,14 @@
 use h3ron::H3Cell;
 use std::collections::HashMap;
+use std::fmt::Debug;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::{stream, StreamExt};
 use h3ron_polars::frame::H3DataFrame;
 use itertools::Itertools;
 use tokio::task::spawn_blocking;
-use tracing::{debug, debug_span, error, trace_span, Instrument};
+use tracing::{debug, debug_span, error, info, trace_span, Instrument};
 
 use ukis_clickhouse_arrow_grpc::{ArrowInterface, QueryInfo};
 
@@ -17,6 +20,7 @@ use crate::clickhouse::compacted_tables::schema::{
 };
 use crate::clickhouse::compacted_tables::temporary_key::TemporaryKey;
 use crate::clickhouse::compacted_tables::{CompactedTablesStore, COL_NAME_H3INDEX};
+use crate::clickhouse::retry::{with_retry, RetryPolicy};
 use crate::clickhouse::H3CellStore;
 use crate::Error;
 
@@ -24,12 +28,76 @@ use crate::Error;
 const COL_NAME_H3INDEX_PARENT_AGG: &str = "h3index_parent_agg";
 const ALIAS_SOURCE_TABLE: &str = "src_table";
 
+/// the max. number of h3indexes of a dataframe to validate before inserting
+const MAX_NUM_H3INDEXES_VALIDATED: usize = 1000;
+
+/// Gets notified about the progress of an insert.
+pub trait InsertObserver: Debug + Send + Sync {
+    /// called after a batch of `num_rows` rows has been written to the
+    /// temporary table `table_name`.
+    fn on_batch_complete(&self, table_name: &str, num_rows: usize, elapsed: Duration);
+
+    /// called after the insert including the copying to the final tables has been completed.
+    /// Dry runs report the number of rows written to the temporary tables.
+    fn on_complete(&self, total_num_rows: usize, total_elapsed: Duration);
+}
+
+/// Logs the progress of inserts using the `info` level
+#[derive(Debug, Default, Clone)]
+pub struct LoggingInsertObserver;
+
+impl InsertObserver for LoggingInsertObserver {
+    fn on_batch_complete(&self, table_name: &str, num_rows: usize, elapsed: Duration) {
+        info!(
+            "inserted {} rows into {} in {:.2}s",
+            num_rows,
+            table_name,
+            elapsed.as_secs_f64()
+        );
+    }
+
+    fn on_complete(&self, total_num_rows: usize, total_elapsed: Duration) {
+        info!(
+            "insert of {} rows completed in {:.2}s",
+            total_num_rows,
+            total_elapsed.as_secs_f64()
+        );
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct InsertOptions {
     pub create_schema: bool,
     pub deduplicate_after_insert: bool,
     pub max_num_rows_per_chunk: usize,
 
+    /// perform the insert including the aggregation of all resolutions in the
+    /// temporary tables, but do not copy the data to the final tables. The number of rows which
+    /// would have been inserted into each table is logged instead.
+    ///
+    /// The schema is still created when `create_schema` is set.
+    pub dry_run: bool,
+
+    /// validate a sample of up to 1000 h3indexes of the dataframe before inserting
+    /// to catch invalid values before they end up in the database.
+    pub validate_h3indexes: bool,
+
+    /// insert the dataframe as it is, without compacting it. This is intended for data which is
+    /// already compacted. The cells coarser than the finest base resolution are inserted
+    /// into the compacted tables.
+    pub skip_compaction: bool,
+
+    /// the max. number of resolutions to insert into the temporary tables concurrently.
+    /// Values of 0 and 1 insert the resolutions one after another.
+    pub max_concurrent_inserts: usize,
+
+    /// observer to report the progress of the insert to
+    pub observer: Option<Arc<dyn InsertObserver>>,
+
+    /// retrying of the creation of the tables, the inserts into the temporary tables and the
+    /// copying to the final tables when these fail with transient errors.
+    pub retry_policy: RetryPolicy,
+
     /// boalean to set to true to abort the insert process
     pub abort: Arc<Mutex<bool>>,
 }
@@ -40,6 +108,12 @@ impl Default for InsertOptions {
             create_schema: true,
             deduplicate_after_insert: true,
             max_num_rows_per_chunk: 1_000_000,
+            dry_run: false,
+            validate_h3indexes: true,
+            skip_compaction: false,
+            max_concurrent_inserts: 1,
+            observer: None,
+            retry_policy: RetryPolicy::default(),
             abort: Arc::new(Mutex::new(false)),
         }
     }
@@ -55,7 +129,7 @@ pub struct Inserter<C> {
 
 impl<C> Inserter<C>
 where
-    C: ArrowInterface + CompactedTablesStore + Send + Sync,
+    C: ArrowInterface + CompactedTablesStore + Clone + Send + Sync,
 {
     pub fn new(
         store: C,
@@ -87,14 +161,22 @@ where
 
     /// This method is a somewhat expensive operation
     pub async fn insert(&mut self, h3df: H3DataFrame<H3Cell>) -> Result<(), Error> {
+        let insert_start = Instant::now();
         let frames_by_resolution = if h3df.dataframe().is_empty() {
             Default::default()
         } else {
-            let disables_compaction = self
-                .schema
-                .columns
-                .iter()
-                .any(|(_, cdef)| cdef.disables_compaction());
+            if self.options.validate_h3indexes {
+                validate_h3indexes_sample(&h3df)?;
+            }
+
+            // schemas without compacted tables have no place to store the compacted cells
+            let disables_compaction = self.options.skip_compaction
+                || !self.schema.use_compaction
+                || self
+                    .schema
+                    .columns
+                    .iter()
+                    .any(|(_, cdef)| cdef.disables_compaction());
 
             let frames_by_resolution = spawn_blocking(move || {
                 // usage of sum aggregation
@@ -113,11 +195,21 @@ where
                 .map(|(res, _)| *res)
                 .max()
                 .ok_or(Error::EmptyCells)?;
-            let max_res_supported = self.schema.max_h3_resolution;
+            let max_res_supported = self.schema.max_h3_resolution()?;
             if max_res_supported < max_res_found {
                 error!("dataframe contains higher resolution ({}) than are supported in the tableset ({})", max_res_found, max_res_supported);
                 return Err(Error::UnsupportedH3Resolution(max_res_found));
             }
+            if !self.schema.use_compaction {
+                // cells at other resolutions would need to go into the compacted tables
+                if let Some((unsupported_res, _)) = frames_by_resolution
+                    .iter()
+                    .find(|(res, _)| **res != max_res_supported)
+                {
+                    error!("dataframe contains cells at resolution {} but the tableset only has tables at resolution {} without compaction", unsupported_res, max_res_supported);
+                    return Err(Error::UnsupportedH3Resolution(*unsupported_res));
+                }
+            }
             frames_by_resolution
         };
         self.check_for_abort()?;
@@ -134,9 +226,14 @@ where
         let tk_opt = Some(self.temporary_key.clone());
 
         if self.options.create_schema {
-            self.store
-                .create_tableset(&self.database_name, &self.schema)
-                .await?;
+            // the tables are created using `IF NOT EXISTS`
+            with_retry(&self.options.retry_policy, "creating the tableset", || {
+                let mut store = self.store.clone();
+                let database_name = self.database_name.as_str();
+                let schema = &self.schema;
+                async move { store.create_tableset(database_name, schema).await }
+            })
+            .await?;
         }
 
         // ensure the temporary schema exists
@@ -149,25 +246,77 @@ where
             .await?;
 
         // insert into temporary tables
-        for (h3_resolution, h3df) in frames_by_resolution {
-            let table = self.schema.build_table(
-                &ResolutionMetadata::new(
-                    h3_resolution,
-                    h3_resolution != self.schema.max_h3_resolution,
-                ),
-                &tk_opt,
-            );
+        let max_h3_resolution = self.schema.max_h3_resolution()?;
+        let batches: Vec<_> = frames_by_resolution
+            .into_iter()
+            .map(|(h3_resolution, h3df)| {
+                let table_name = self
+                    .schema
+                    .build_table(
+                        &ResolutionMetadata::new(h3_resolution, h3_resolution != max_h3_resolution),
+                        &tk_opt,
+                    )
+                    .to_table_name();
+                (h3_resolution, table_name, h3df)
+            })
+            .collect();
 
+        self.check_for_abort()?;
+        let mut total_num_rows = 0;
+        let mut batch_results = stream::iter(batches.into_iter().map(
+            |(h3_resolution, table_name, h3df)| {
+                let store = self.store.clone();
+                let database_name = self.database_name.as_str();
+                let max_num_rows_per_chunk = self.options.max_num_rows_per_chunk.max(1);
+                let retry_policy = &self.options.retry_policy;
+                async move {
+                    let num_rows = h3df.dataframe().height();
+                    let batch_start = Instant::now();
+
+                    // retrying individual chunks avoids writing the already inserted chunks twice
+                    let mut offset = 0;
+                    while offset < num_rows {
+                        let chunk_df = h3df
+                            .dataframe()
+                            .slice(offset as i64, max_num_rows_per_chunk);
+                        offset += max_num_rows_per_chunk;
+                        with_retry(retry_policy, "inserting into a temporary table", || {
+                            let mut store = store.clone();
+                            let chunk_h3df = H3DataFrame::from_dataframe_nonvalidated(
+                                chunk_df.clone(),
+                                h3df.h3index_column_name(),
+                            );
+                            let table_name = table_name.as_str();
+                            async move {
+                                store
+                                    .insert_h3dataframe(database_name, table_name, chunk_h3df)
+                                    .await
+                            }
+                        })
+                        .await?;
+                    }
+                    Ok::<_, Error>((h3_resolution, table_name, num_rows, batch_start.elapsed()))
+                }
+            },
+        ))
+        .buffer_unordered(self.options.max_concurrent_inserts.max(1));
+        while let Some(batch_result) = batch_results.next().await {
+            let (h3_resolution, table_name, num_rows, batch_elapsed) = batch_result?;
+            total_num_rows += num_rows;
+            debug!(
+                table_name = table_name.as_str(),
+                rows_inserted = num_rows,
+                duration_ms = batch_elapsed.as_millis() as u64,
+                h3_resolution,
+                "inserted batch into temporary table"
+            );
+            if let Some(observer) = &self.options.observer {
+                observer.on_batch_complete(&table_name, num_rows, batch_elapsed);
+            }
             self.check_for_abort()?;
-            self.store
-                .insert_h3dataframe_chunked(
-                    self.database_name.as_str(),
-                    table.to_table_name(),
-                    h3df,
-                    self.options.max_num_rows_per_chunk,
-                )
-                .await?;
         }
+        // release the borrow of self held by the stream
+        drop(batch_results);
 
         let resolution_metadata = self.schema.get_resolution_metadata()?;
 
@@ -180,50 +329,71 @@ where
             ))
             .await?;
 
-        // move rows to non-temporary tables
-        self.check_for_abort()?;
-        self.copy_data_from_temporary(&resolution_metadata)
-            .instrument(debug_span!(
-                "Copying data from temporary tables",
-                temporary_key = tk_str.as_str()
-            ))
-            .await?;
+        if self.options.dry_run {
+            self.check_for_abort()?;
+            self.report_dry_run(&resolution_metadata)
+                .instrument(debug_span!(
+                    "Reporting dry-run insert",
+                    temporary_key = tk_str.as_str()
+                ))
+                .await?;
+        } else {
+            // move rows to non-temporary tables
+            self.check_for_abort()?;
+            self.copy_data_from_temporary(&resolution_metadata)
+                .instrument(debug_span!(
+                    "Copying data from temporary tables",
+                    temporary_key = tk_str.as_str()
+                ))
+                .await?;
 
-        // deduplicate
-        self.check_for_abort()?;
-        if self.options.deduplicate_after_insert {
-            if let Err(e) = deduplicate_partitions_based_on_temporary_tables(
-                &mut self.store,
-                &self.database_name,
-                &self.schema,
-                &resolution_metadata,
-                &tk_opt,
-            )
-            .instrument(debug_span!(
-                "De-duplicating touched partitions",
-                temporary_key = tk_str.as_str()
-            ))
-            .await
-            {
-                match e {
-                    Error::MissingPrecondidtionsForPartialOptimization => {
-                        deduplicate_full(
-                            &mut self.store,
-                            &self.database_name,
-                            &self.schema,
-                            &resolution_metadata,
-                        )
-                        .instrument(debug_span!(
-                            "De-duplicating complete tables",
-                            temporary_key = tk_str.as_str()
-                        ))
-                        .await?
+            // deduplicate
+            self.check_for_abort()?;
+            if self.options.deduplicate_after_insert {
+                if let Err(e) = deduplicate_partitions_based_on_temporary_tables(
+                    &mut self.store,
+                    &self.database_name,
+                    &self.schema,
+                    &resolution_metadata,
+                    &tk_opt,
+                )
+                .instrument(debug_span!(
+                    "De-duplicating touched partitions",
+                    temporary_key = tk_str.as_str()
+                ))
+                .await
+                {
+                    match e {
+                        Error::MissingPrecondidtionsForPartialOptimization => {
+                            deduplicate_full(
+                                &mut self.store,
+                                &self.database_name,
+                                &self.schema,
+                                &resolution_metadata,
+                            )
+                            .instrument(debug_span!(
+                                "De-duplicating complete tables",
+                                temporary_key = tk_str.as_str()
+                            ))
+                            .await?
+                        }
+                        _ => return Err(e),
                     }
-                    _ => return Err(e),
                 }
             }
         }
 
+        let total_elapsed = insert_start.elapsed();
+        info!(
+            total_rows = total_num_rows,
+            total_duration_ms = total_elapsed.as_millis() as u64,
+            tableset_basename = self.schema.name.as_str(),
+            dry_run = self.options.dry_run,
+            "insert completed"
+        );
+        if let Some(observer) = &self.options.observer {
+            observer.on_complete(total_num_rows, total_elapsed);
+        }
         Ok(())
     }
 
@@ -233,17 +403,30 @@ where
             .schema
             .build_create_statements(&Some(self.temporary_key.clone()))?
         {
-            self.store
-                .execute_query_checked(QueryInfo {
-                    query: create_stmt,
-                    database: self.database_name.clone(),
-                    ..Default::default()
-                })
+            self.execute_with_retry("creating a temporary table", create_stmt)
                 .await?;
         }
         Ok(())
     }
 
+    /// execute a statement which can be repeated without changing the result, retrying it
+    /// according to the retry policy of the options.
+    async fn execute_with_retry(&self, operation_name: &str, query: String) -> Result<(), Error> {
+        with_retry(&self.options.retry_policy, operation_name, || {
+            let mut store = self.store.clone();
+            let query_info = QueryInfo {
+                query: query.clone(),
+                database: self.database_name.clone(),
+                ..Default::default()
+            };
+            async move {
+                store.execute_query_checked(query_info).await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
     async fn drop_temporary_tables(&mut self) -> Result<(), Error> {
         let mut finish_result = Ok(());
         // remove the temporary tables
@@ -365,7 +548,7 @@ where
 
                 // the compacted tables in between.
                 for r in (target_resolution + 1)..=source_resolution {
-                    if resolutions_to_aggregate.contains(&r) {
+                    if self.schema.use_compaction && resolutions_to_aggregate.contains(&r) {
                         source_tables.push((
                             r,
                             self.schema
@@ -563,21 +746,56 @@ where
                 .schema
                 .build_table(resolution_metadata, &None)
                 .to_table_name();
-            self.store
-                .execute_query_checked(QueryInfo {
-                    query: format!(
-                        "insert into {} ({}) select {} from {}",
-                        table_to, columns, columns, table_from
-                    ),
+            self.execute_with_retry(
+                "copying data from a temporary table",
+                format!(
+                    "insert into {} ({}) select {} from {}",
+                    table_to, columns, columns, table_from
+                ),
+            )
+            .instrument(trace_span!(
+                "copying data from temporary table to final table",
+                table_from = table_from.as_str(),
+                table_to = table_to.as_str()
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// log the number of rows which would be copied from the temporary tables to the final tables
+    async fn report_dry_run(
+        &mut self,
+        resolution_metadata_slice: &[ResolutionMetadata],
+    ) -> Result<(), Error> {
+        let tk = Some(self.temporary_key.clone());
+        for resolution_metadata in resolution_metadata_slice.iter() {
+            let table_from = self
+                .schema
+                .build_table(resolution_metadata, &tk)
+                .to_table_name();
+            let table_to = self
+                .schema
+                .build_table(resolution_metadata, &None)
+                .to_table_name();
+            let num_rows = self
+                .store
+                .execute_into_dataframe(QueryInfo {
+                    query: format!("select count(*) as num_rows from {}", table_from),
                     database: self.database_name.clone(),
                     ..Default::default()
                 })
-                .instrument(trace_span!(
-                    "copying data from temporary table to final table",
-                    table_from = table_from.as_str(),
-                    table_to = table_to.as_str()
-                ))
-                .await?;
+                .await?
+                .column("num_rows")?
+                .u64()?
+                .into_iter()
+                .next()
+                .flatten()
+                .unwrap_or(0);
+            info!(
+                "dry-run: would insert {} rows into table {}",
+                num_rows, table_to
+            );
         }
         Ok(())
     }
@@ -592,3 +810,429 @@ where
             .await
     }
 }
+
+/// validate an evenly distributed sample of up to `MAX_NUM_H3INDEXES_VALIDATED` h3indexes
+fn validate_h3indexes_sample(h3df: &H3DataFrame<H3Cell>) -> Result<(), Error> {
+    let h3indexes = h3df.dataframe().column(h3df.h3index_column_name())?.u64()?;
+    let step = (h3indexes.len() / MAX_NUM_H3INDEXES_VALIDATED).max(1);
+    for h3index in h3indexes
+        .into_iter()
+        .step_by(step)
+        .take(MAX_NUM_H3INDEXES_VALIDATED)
+    {
+        let h3index = h3index.ok_or(Error::MissingIndexValue)?;
+        if H3Cell::try_from(h3index).is_err() {
+            return Err(Error::InvalidH3Index(h3index));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use geo_types::Coord;
+    use h3ron::{H3Cell, Index};
+    use h3ron_polars::frame::H3DataFrame;
+    use h3ron_polars::FromIndexIterator;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use ukis_clickhouse_arrow_grpc::mock::{MockClient, RecordedCall};
+
+    use crate::clickhouse::compacted_tables::schema::{
+        ClickhouseDataType, ColumnDefinition, CompactedTableSchema, CompactedTableSchemaBuilder,
+        SimpleColumn,
+    };
+    use crate::clickhouse::compacted_tables::{
+        CompactedTablesStore, InsertObserver, InsertOptions, COL_NAME_H3INDEX,
+    };
+    use crate::clickhouse::retry::RetryPolicy;
+    use crate::Error;
+
+    /// schema with a single base resolution matching the dataframes of `make_h3dataframe`
+    fn elephants_schema_builder() -> CompactedTableSchemaBuilder {
+        CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5])
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+    }
+
+    fn elephants_schema() -> CompactedTableSchema {
+        elephants_schema_builder().build().unwrap()
+    }
+
+    fn make_h3dataframe(h3_resolution: u8) -> H3DataFrame<H3Cell> {
+        let cell =
+            H3Cell::from_coordinate(Coord::from((22.8996, -19.3325)), h3_resolution).unwrap();
+        let neighbour = cell
+            .grid_disk(1)
+            .unwrap()
+            .iter()
+            .find(|c| *c != cell)
+            .unwrap();
+
+        let mut index_series = Series::from_index_iter(vec![cell, neighbour].into_iter());
+        index_series.rename(COL_NAME_H3INDEX);
+        let df = DataFrame::new(vec![
+            index_series,
+            Series::new("elephant_count", &[2_u32, 3_u32]),
+        ])
+        .unwrap();
+        H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX).unwrap()
+    }
+
+    #[tokio::test]
+    async fn insert_without_compacted_resolutions() {
+        let schema = elephants_schema_builder()
+            .h3_base_resolutions(vec![4, 5])
+            .use_compacted_resolutions(false)
+            .build()
+            .unwrap();
+
+        // the seven children of a cell get compacted when compaction is enabled
+        let parent = H3Cell::from_coordinate(Coord::from((22.8996, -19.3325)), 4).unwrap();
+        let children: Vec<H3Cell> = parent.get_children(5).unwrap().iter().collect();
+        let mut index_series = Series::from_index_iter(children.into_iter());
+        index_series.rename(COL_NAME_H3INDEX);
+        let df = DataFrame::new(vec![
+            index_series,
+            Series::new("elephant_count", &[1_u32; 7]),
+        ])
+        .unwrap();
+        let h3df = H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX).unwrap();
+
+        let mut client = MockClient::new();
+        client
+            .insert_h3dataframe_into_tableset("db", &schema, h3df, InsertOptions::default())
+            .await
+            .unwrap();
+
+        assert!(client
+            .calls()
+            .iter()
+            .all(|call| !format!("{:?}", call).contains("_compacted")));
+
+        // cells at resolutions other than the max resolution can not be stored
+        assert!(client
+            .insert_h3dataframe_into_tableset(
+                "db",
+                &schema,
+                make_h3dataframe(4),
+                InsertOptions::default()
+            )
+            .await
+            .is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingInsertObserver {
+        batches: Mutex<Vec<(String, usize)>>,
+        total_num_rows: Mutex<Option<usize>>,
+    }
+
+    impl InsertObserver for RecordingInsertObserver {
+        fn on_batch_complete(&self, table_name: &str, num_rows: usize, _elapsed: Duration) {
+            self.batches
+                .lock()
+                .unwrap()
+                .push((table_name.to_string(), num_rows));
+        }
+
+        fn on_complete(&self, total_num_rows: usize, _total_elapsed: Duration) {
+            *self.total_num_rows.lock().unwrap() = Some(total_num_rows);
+        }
+    }
+
+    #[tokio::test]
+    async fn insert_notifies_observer() {
+        let schema = elephants_schema();
+
+        let observer = Arc::new(RecordingInsertObserver::default());
+        let options = InsertOptions {
+            observer: Some(observer.clone()),
+            ..Default::default()
+        };
+        let mut client = MockClient::new();
+        client
+            .insert_h3dataframe_into_tableset("db", &schema, make_h3dataframe(5), options)
+            .await
+            .unwrap();
+
+        let batches = observer.batches.lock().unwrap().clone();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].0.starts_with("elephants_05_base_tmp"));
+        assert_eq!(batches[0].1, 2);
+        assert_eq!(*observer.total_num_rows.lock().unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn insert_skip_compaction() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5, 6])
+            .build()
+            .unwrap();
+
+        // pre-compacted data
+        let h3df = {
+            let h3df5 = make_h3dataframe(5);
+            let cell5 = h3df5
+                .dataframe()
+                .column(COL_NAME_H3INDEX)
+                .unwrap()
+                .u64()
+                .unwrap()
+                .get(0)
+                .unwrap();
+            let cell6 = H3Cell::new(cell5)
+                .get_children(6)
+                .unwrap()
+                .iter()
+                .next()
+                .unwrap();
+            let df = DataFrame::new(vec![Series::new(
+                COL_NAME_H3INDEX,
+                &[cell5, cell6.h3index()],
+            )])
+            .unwrap();
+            H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX).unwrap()
+        };
+
+        let mut client = MockClient::new();
+        client
+            .insert_h3dataframe_into_tableset(
+                "db",
+                &schema,
+                h3df,
+                InsertOptions {
+                    skip_compaction: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let inserted_tables: Vec<_> = client
+            .calls()
+            .iter()
+            .filter_map(|call| match call {
+                RecordedCall::Insert { table, df, .. } => Some((table.clone(), df.shape().0)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inserted_tables.len(), 2);
+        assert!(inserted_tables.iter().any(|(table, num_rows)| table
+            .starts_with("elephants_05_compacted_tmp")
+            && *num_rows == 1));
+        assert!(inserted_tables
+            .iter()
+            .any(|(table, num_rows)| table.starts_with("elephants_06_base_tmp") && *num_rows == 1));
+
+        // cells finer than the finest base resolution are still rejected
+        assert!(matches!(
+            client
+                .insert_h3dataframe_into_tableset(
+                    "db",
+                    &schema,
+                    make_h3dataframe(7),
+                    InsertOptions {
+                        skip_compaction: true,
+                        ..Default::default()
+                    },
+                )
+                .await,
+            Err(Error::UnsupportedH3Resolution(7))
+        ));
+    }
+
+    #[tokio::test]
+    async fn insert_concurrently() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_resolution_range(4, 8)
+            .build()
+            .unwrap();
+
+        // one cell per resolution to get one batch per temporary table
+        let make_h3df = || {
+            let cell = H3Cell::from_coordinate(Coord::from((22.8996, -19.3325)), 8).unwrap();
+            let cells: Vec<_> = (4..8)
+                .map(|h3_resolution| cell.get_parent(h3_resolution).unwrap())
+                .chain(std::iter::once(cell))
+                .collect();
+            let mut index_series = Series::from_index_iter(cells.into_iter());
+            index_series.rename(COL_NAME_H3INDEX);
+            H3DataFrame::from_dataframe(
+                DataFrame::new(vec![index_series]).unwrap(),
+                COL_NAME_H3INDEX,
+            )
+            .unwrap()
+        };
+
+        let observer = Arc::new(RecordingInsertObserver::default());
+        let options = InsertOptions {
+            skip_compaction: true,
+            max_concurrent_inserts: 3,
+            observer: Some(observer.clone()),
+            ..Default::default()
+        };
+        let mut client = MockClient::new();
+        client
+            .insert_h3dataframe_into_tableset("db", &schema, make_h3df(), options)
+            .await
+            .unwrap();
+
+        let inserted_tables: Vec<_> = client
+            .calls()
+            .iter()
+            .filter_map(|call| match call {
+                RecordedCall::Insert { table, .. } => Some(table.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inserted_tables.len(), 5);
+        for expected_prefix in [
+            "elephants_04_compacted_tmp",
+            "elephants_05_compacted_tmp",
+            "elephants_06_compacted_tmp",
+            "elephants_07_compacted_tmp",
+            "elephants_08_base_tmp",
+        ] {
+            assert!(inserted_tables
+                .iter()
+                .any(|table| table.starts_with(expected_prefix)));
+        }
+        assert_eq!(observer.batches.lock().unwrap().len(), 5);
+        assert_eq!(*observer.total_num_rows.lock().unwrap(), Some(5));
+
+        // failures of a single concurrent insert fail the whole insert
+        let mut client = MockClient::new();
+        client.respond_with_exception("insert into elephants_06_compacted_tmp", "failed");
+        assert!(client
+            .insert_h3dataframe_into_tableset(
+                "db",
+                &schema,
+                make_h3df(),
+                InsertOptions {
+                    skip_compaction: true,
+                    max_concurrent_inserts: 3,
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn insert_rejects_invalid_h3indexes() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5])
+            .build()
+            .unwrap();
+
+        let valid_h3index = make_h3dataframe(5)
+            .dataframe()
+            .column(COL_NAME_H3INDEX)
+            .unwrap()
+            .u64()
+            .unwrap()
+            .get(0)
+            .unwrap();
+        let df =
+            DataFrame::new(vec![Series::new(COL_NAME_H3INDEX, &[valid_h3index, 0_u64])]).unwrap();
+        let h3df = H3DataFrame::from_dataframe_nonvalidated(df, COL_NAME_H3INDEX);
+
+        let mut client = MockClient::new();
+        let res = client
+            .insert_h3dataframe_into_tableset("db", &schema, h3df, InsertOptions::default())
+            .await;
+        assert!(matches!(res, Err(Error::InvalidH3Index(0))));
+
+        // nothing has been inserted
+        assert!(client
+            .calls()
+            .iter()
+            .all(|call| !matches!(call, RecordedCall::Insert { .. })));
+    }
+
+    #[tokio::test]
+    async fn insert_retries_transient_errors() {
+        let mut client = MockClient::new();
+        client.respond_with_exception(
+            "insert into elephants_05_base_tmp",
+            "Code: 202. DB::Exception: Too many simultaneous queries",
+        );
+        let options = InsertOptions {
+            retry_policy: RetryPolicy {
+                max_attempts: 2,
+                initial_backoff_ms: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = client
+            .insert_h3dataframe_into_tableset(
+                "db",
+                &elephants_schema(),
+                make_h3dataframe(5),
+                options,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::ClickhouseException(_))));
+
+        let num_inserts = client
+            .calls()
+            .iter()
+            .filter(|call| matches!(call, RecordedCall::Insert { .. }))
+            .count();
+        assert_eq!(num_inserts, 2);
+    }
+
+    #[tokio::test]
+    async fn insert_dry_run() {
+        let schema = elephants_schema();
+
+        let mut client = MockClient::new();
+        client.respond_with_dataframe(
+            "as num_rows",
+            DataFrame::new(vec![Series::new("num_rows", &[2_u64])]).unwrap(),
+        );
+
+        let observer = Arc::new(RecordingInsertObserver::default());
+        let options = InsertOptions {
+            dry_run: true,
+            observer: Some(observer.clone()),
+            ..Default::default()
+        };
+        client
+            .insert_h3dataframe_into_tableset("db", &schema, make_h3dataframe(5), options)
+            .await
+            .unwrap();
+
+        // the completion is reported for dry runs as well
+        assert_eq!(*observer.total_num_rows.lock().unwrap(), Some(2));
+
+        let queries = client.queries();
+
+        // the schema has been created
+        assert!(queries
+            .iter()
+            .any(|q| q.starts_with("CREATE TABLE IF NOT EXISTS elephants_05_base ")));
+
+        // nothing has been copied into the final tables
+        assert!(!queries
+            .iter()
+            .any(|q| q.starts_with("insert into elephants_05_base ")));
+
+        // temporary tables have been cleaned up
+        assert!(queries
+            .iter()
+            .any(|q| q.starts_with("drop table if exists elephants_05_base_tmp")));
+    }
+}
@@ -1,26 +1,31 @@
+use std::any::type_name;
 use std::cmp::Ordering;
 use std::default::Default;
 
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use tokio::task::spawn_blocking;
 use tracing::{debug, info_span, warn, Instrument};
 
-use h3ron::collections::{H3CellSet, HashMap};
+use h3ron::collections::{H3CellSet, HashMap, HashSet};
 use h3ron::iter::change_resolution;
 use h3ron::{H3Cell, Index};
 use h3ron_polars::frame::H3DataFrame;
-use itertools::join;
+use itertools::{join, Itertools};
 use polars::prelude::{DataFrame, NamedFrom, Series};
 pub use tableset::{Table, TableSet, TableSpec};
 use ukis_clickhouse_arrow_grpc::{ArrowInterface, QueryInfo};
 
-pub use crate::clickhouse::compacted_tables::insert::InsertOptions;
 use crate::clickhouse::compacted_tables::insert::Inserter;
+pub use crate::clickhouse::compacted_tables::insert::{
+    InsertObserver, InsertOptions, LoggingInsertObserver,
+};
 use crate::clickhouse::compacted_tables::optimize::deduplicate_full;
-use crate::clickhouse::compacted_tables::schema::CompactedTableSchema;
+use crate::clickhouse::compacted_tables::schema::{validate_table_name, CompactedTableSchema};
 use crate::clickhouse::compacted_tables::select::BuildCellQueryString;
 pub use crate::clickhouse::compacted_tables::select::TableSetQuery;
 use crate::clickhouse::compacted_tables::tableset::{find_tablesets, LoadTableSet};
+use crate::clickhouse::retry::{with_retry, RetryPolicy};
 use crate::Error;
 
 mod insert;
@@ -34,11 +39,58 @@ pub mod traversal;
 /// the column name which must be used for h3indexes.
 pub const COL_NAME_H3INDEX: &str = "h3index";
 
+/// the number of queries [`CompactedTablesStore::list_tablesets`] runs concurrently
+/// to discover the columns of the tablesets.
+pub const DEFAULT_MAX_CONCURRENT_QUERIES: usize = 8;
+
+/// Priority of queries on the ClickHouse server.
+///
+/// Sets the `priority` setting of ClickHouse. While queries with a higher priority are running,
+/// queries with a lower priority get paused. This allows heavy background loads to yield to
+/// interactive queries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QueryPriority {
+    High,
+    /// queries without any priority
+    Normal,
+    Low,
+}
+
+impl Default for QueryPriority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl QueryPriority {
+    /// set the priority in the settings of the query
+    pub fn apply_to(&self, query_info: &mut QueryInfo) {
+        // lower values mean higher priorities, 0 disables the priority of the query.
+        let priority = match self {
+            Self::High => 1,
+            Self::Normal => return,
+            Self::Low => 10,
+        };
+        query_info
+            .settings
+            .insert("priority".to_string(), priority.to_string());
+    }
+}
+
 pub struct QueryOptions {
     pub query: TableSetQuery,
     pub cells: Vec<H3Cell>,
     pub h3_resolution: u8,
     pub do_uncompact: bool,
+
+    /// query the tables using the `FINAL` modifier to get fully merged results
+    /// even before ClickHouse has merged the parts in the background.
+    ///
+    /// This makes the query significantly more expensive for large tables.
+    pub final_modifier: bool,
+
+    /// priority of the queries on the ClickHouse server
+    pub priority: QueryPriority,
 }
 
 impl QueryOptions {
@@ -49,6 +101,8 @@ impl QueryOptions {
             cells,
             h3_resolution,
             do_uncompact: true,
+            final_modifier: false,
+            priority: QueryPriority::default(),
         }
     }
 }
@@ -62,6 +116,16 @@ pub trait CompactedTablesStore {
     where
         S: AsRef<str> + Sync + Send;
 
+    /// list the tablesets like [`CompactedTablesStore::list_tablesets`], running up to
+    /// `max_concurrent` of the queries to discover the columns of the tablesets at once.
+    async fn list_tablesets_concurrently<S>(
+        &mut self,
+        database_name: S,
+        max_concurrent: usize,
+    ) -> Result<HashMap<String, TableSet>, Error>
+    where
+        S: AsRef<str> + Sync + Send;
+
     async fn get_tableset<S1, S2>(
         &mut self,
         database_name: S1,
@@ -77,7 +141,15 @@ pub trait CompactedTablesStore {
             .ok_or_else(|| Error::TableSetNotFound(tableset_name.as_ref().to_string()))
     }
 
-    async fn drop_tableset<S, TS>(&mut self, database_name: S, tableset: TS) -> Result<(), Error>
+    /// drop all tables of the tableset.
+    ///
+    /// Returns the number of dropped tables. Tablesets which do not exist are not an error,
+    /// `0` is returned for these.
+    async fn drop_tableset<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+    ) -> Result<usize, Error>
     where
         S: AsRef<str> + Send + Sync,
         TS: LoadTableSet + Send + Sync;
@@ -90,6 +162,38 @@ pub trait CompactedTablesStore {
     where
         S: AsRef<str> + Sync + Send;
 
+    /// copy all tables of the tableset described by `schema` including their data to a new
+    /// tableset named `target_tableset_name`.
+    ///
+    /// The target tables are created from the `schema`, including its cluster and replication
+    /// settings. Fails when a tableset named `target_tableset_name` already exists. The tables
+    /// created so far are dropped again when copying fails.
+    ///
+    /// The copy is not atomic: tables created concurrently under the target name after the
+    /// existence check are not detected, and writes to the source tables during the copy may
+    /// be missing from the target tables.
+    async fn copy_tableset<S, S2>(
+        &mut self,
+        database_name: S,
+        schema: &CompactedTableSchema,
+        target_tableset_name: S2,
+    ) -> Result<(), Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        S2: AsRef<str> + Send + Sync;
+
+    /// copy the tableset like [`CompactedTablesStore::copy_tableset`] and drop the tables
+    /// of the source tableset afterwards.
+    async fn move_tableset<S, S2>(
+        &mut self,
+        database_name: S,
+        schema: &CompactedTableSchema,
+        target_tableset_name: S2,
+    ) -> Result<(), Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        S2: AsRef<str> + Send + Sync;
+
     async fn insert_h3dataframe_into_tableset<S>(
         &mut self,
         database_name: S,
@@ -118,6 +222,21 @@ pub trait CompactedTablesStore {
         S: AsRef<str> + Send + Sync,
         TS: LoadTableSet + Send + Sync;
 
+    /// find the cells for which data exists in the tableset using a single query.
+    ///
+    /// The `cells` are brought to `h3_resolution` before the check. Data stored in
+    /// the compacted tables of coarser resolutions is taken into account.
+    async fn tableset_contains_cells<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+        cells: Vec<H3Cell>,
+        h3_resolution: u8,
+    ) -> Result<H3CellSet, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync;
+
     /// get stats about the number of cells and compacted cells in all the
     /// resolutions of the tableset
     async fn tableset_stats<S, TS>(
@@ -128,6 +247,33 @@ pub trait CompactedTablesStore {
     where
         S: AsRef<str> + Send + Sync,
         TS: LoadTableSet + Send + Sync;
+
+    /// the number of rows stored in each table of the tableset, keyed by the table name.
+    ///
+    /// The tables are counted concurrently. See [`total_row_count`] for the sum over all tables.
+    async fn tableset_row_counts<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+    ) -> Result<HashMap<String, u64>, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync;
+
+    /// fraction of all possible cells of the given resolution which are stored in the
+    /// base table of that resolution.
+    ///
+    /// Fails with [`Error::H3ResolutionNotInTableSet`] when the tableset has no base table
+    /// of the resolution.
+    async fn resolution_coverage_fraction<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+        h3_resolution: u8,
+    ) -> Result<f64, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync;
 }
 
 #[async_trait]
@@ -142,53 +288,104 @@ where
     where
         S: AsRef<str> + Sync + Send,
     {
+        self.list_tablesets_concurrently(database_name, DEFAULT_MAX_CONCURRENT_QUERIES)
+            .await
+    }
+
+    async fn list_tablesets_concurrently<S>(
+        &mut self,
+        database_name: S,
+        max_concurrent: usize,
+    ) -> Result<HashMap<String, TableSet>, Error>
+    where
+        S: AsRef<str> + Sync + Send,
+    {
+        // listing the tablesets is read-only, so failed queries can safely be retried
+        let retry_policy = RetryPolicy::default();
+
         let mut tablesets = {
-            let tableset_df = self
-                .execute_into_dataframe(QueryInfo {
+            let tableset_df = with_retry(&retry_policy, "listing the tables", || {
+                let mut client = self.clone();
+                let query_info = QueryInfo {
                     query: format!(
                         "select table from system.columns where name = '{}' and database = currentDatabase()",
                         COL_NAME_H3INDEX
                     ),
                     database: database_name.as_ref().to_string(),
                     ..Default::default()
-                })
-                .await?;
+                };
+                async move {
+                    client
+                        .execute_into_dataframe(query_info)
+                        .await
+                        .map_err(Error::from)
+                }
+            })
+            .await?;
 
-            let tablenames: Vec<String> = tableset_df
-                .column("table")?
-                .utf8()?
-                .into_iter()
-                .flatten()
-                .map(|table_name| table_name.to_string())
-                .collect();
-            find_tablesets(&tablenames)
+            find_tablesets(tableset_df.column("table")?.utf8()?.into_iter().flatten())
         };
 
         // find the columns for the tablesets
-        for (ts_name, ts) in tablesets.iter_mut() {
-            let set_table_names = itertools::join(
-                ts.tables()
-                    .iter()
-                    .map(|t| format!("'{}'", t.to_table_name())),
-                ", ",
-            );
-
-            let columns_df = self
-                .execute_into_dataframe(QueryInfo {
-                    query: format!(
-                        "select name, type, count(*) as c
+        let column_queries: Vec<_> = tablesets
+            .iter()
+            .map(|(ts_name, ts)| {
+                let set_table_names = itertools::join(
+                    ts.tables()
+                        .iter()
+                        .map(|t| format!("'{}'", t.to_table_name())),
+                    ", ",
+                );
+                let query = format!(
+                    "select name, type, count(*) as c
                 from system.columns
                 where table in ({})
                 and database = currentDatabase()
                 and not startsWith(name, '{}')
                 group by name, type",
-                        set_table_names, COL_NAME_H3INDEX
-                    ),
+                    set_table_names, COL_NAME_H3INDEX
+                );
+                (ts_name.clone(), query)
+            })
+            .collect();
+
+        let columns_dfs: Vec<(String, DataFrame)> =
+            stream::iter(column_queries.into_iter().map(|(ts_name, query)| {
+                let query_info = QueryInfo {
+                    query,
                     database: database_name.as_ref().to_string(),
                     ..Default::default()
-                })
-                .await?;
+                };
+                let client = self.clone();
+                let retry_policy = &retry_policy;
+                async move {
+                    let columns_df = with_retry(
+                        retry_policy,
+                        "discovering the columns of a tableset",
+                        || {
+                            let mut client = client.clone();
+                            let query_info = query_info.clone();
+                            async move {
+                                client
+                                    .execute_into_dataframe(query_info)
+                                    .await
+                                    .map_err(Error::from)
+                            }
+                        },
+                    )
+                    .await?;
+                    Ok::<_, Error>((ts_name, columns_df))
+                }
+            }))
+            .buffer_unordered(max_concurrent.max(1))
+            .try_collect()
+            .await?;
 
+        for (ts_name, columns_df) in columns_dfs {
+            let ts = match tablesets.get_mut(&ts_name) {
+                Some(ts) => ts,
+                None => continue,
+            };
             for ((column_name, table_count_with_column), column_type) in columns_df
                 .column("name")?
                 .utf8()?
@@ -212,7 +409,7 @@ where
         Ok(tablesets)
     }
 
-    async fn drop_tableset<S, TS>(&mut self, database_name: S, tableset: TS) -> Result<(), Error>
+    async fn drop_tableset<S, TS>(&mut self, database_name: S, tableset: TS) -> Result<usize, Error>
     where
         S: AsRef<str> + Send + Sync,
         TS: LoadTableSet + Send + Sync,
@@ -222,6 +419,7 @@ where
             .await
         {
             Ok(tableset) => {
+                let mut num_dropped = 0;
                 for table in tableset
                     .base_tables()
                     .iter()
@@ -233,11 +431,12 @@ where
                         ..Default::default()
                     })
                     .await?;
+                    num_dropped += 1;
                 }
-                Ok(())
+                Ok(num_dropped)
             }
             Err(e) => match e {
-                Error::TableSetNotFound(_) => Ok(()),
+                Error::TableSetNotFound(_) => Ok(0),
                 _ => Err(e),
             },
         };
@@ -262,6 +461,109 @@ where
         Ok(())
     }
 
+    async fn copy_tableset<S, S2>(
+        &mut self,
+        database_name: S,
+        schema: &CompactedTableSchema,
+        target_tableset_name: S2,
+    ) -> Result<(), Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        S2: AsRef<str> + Send + Sync,
+    {
+        // fail early when the source tableset does not exist
+        self.get_tableset(database_name.as_ref(), &schema.name)
+            .await?;
+        validate_table_name(type_name::<TableSet>(), target_tableset_name.as_ref())?;
+        if self
+            .list_tablesets(database_name.as_ref())
+            .await?
+            .contains_key(target_tableset_name.as_ref())
+        {
+            return Err(Error::TableSetAlreadyExists(
+                target_tableset_name.as_ref().to_string(),
+            ));
+        }
+
+        let mut target_schema = schema.clone();
+        target_schema.name = target_tableset_name.as_ref().to_string();
+        let column_names = schema.columns.keys().sorted().join(", ");
+
+        let mut created_tables = vec![];
+        let result = async {
+            for resolution_metadata in schema.get_resolution_metadata()? {
+                let source_table = schema.build_table(&resolution_metadata, &None);
+                let target_table = target_schema.build_table(&resolution_metadata, &None);
+                debug!(
+                    "copying table {} to {}",
+                    source_table.to_table_name(),
+                    target_table.to_table_name()
+                );
+
+                self.execute_query_checked(QueryInfo {
+                    query: target_schema.build_create_statement(&target_table)?,
+                    database: database_name.as_ref().to_string(),
+                    ..Default::default()
+                })
+                .await?;
+                let insert_query = format!(
+                    "insert into {} ({}) select {} from {}",
+                    target_table.to_table_name(),
+                    column_names,
+                    column_names,
+                    source_table.to_table_name()
+                );
+                created_tables.push(target_table);
+                self.execute_query_checked(QueryInfo {
+                    query: insert_query,
+                    database: database_name.as_ref().to_string(),
+                    ..Default::default()
+                })
+                .await?;
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        if result.is_err() {
+            // do not leave a partial copy behind
+            for table in created_tables.iter() {
+                if let Err(e) = self
+                    .execute_query_checked(QueryInfo {
+                        query: target_schema.build_drop_statement(table),
+                        database: database_name.as_ref().to_string(),
+                        ..Default::default()
+                    })
+                    .await
+                {
+                    warn!(
+                        "dropping table {} of the failed copy failed: {}",
+                        table.to_table_name(),
+                        e
+                    );
+                }
+            }
+        }
+        result
+    }
+
+    async fn move_tableset<S, S2>(
+        &mut self,
+        database_name: S,
+        schema: &CompactedTableSchema,
+        target_tableset_name: S2,
+    ) -> Result<(), Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        S2: AsRef<str> + Send + Sync,
+    {
+        self.copy_tableset(database_name.as_ref(), schema, target_tableset_name)
+            .await?;
+        self.drop_tableset(database_name, schema.name.as_str())
+            .await?;
+        Ok(())
+    }
+
     async fn insert_h3dataframe_into_tableset<S>(
         &mut self,
         database_name: S,
@@ -351,21 +653,25 @@ where
                     &tableset,
                     query_options.h3_resolution,
                     &query_options.cells,
+                    query_options.final_modifier,
                 )
                 .map(|query_string| (query_string, query_options.cells))
         })
         .await??;
 
-        let df = self
-            .execute_into_dataframe(QueryInfo {
-                query: query_string,
-                database: database_name.as_ref().to_string(),
-                ..Default::default()
-            })
-            .await?;
+        let mut query_info = QueryInfo {
+            query: query_string,
+            database: database_name.as_ref().to_string(),
+            ..Default::default()
+        };
+        query_options.priority.apply_to(&mut query_info);
+        let df = self.execute_into_dataframe(query_info).await?;
         let h3df = H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX)?;
 
-        let out_h3df = if query_options.do_uncompact {
+        let out_h3df = if h3df.dataframe().height() == 0 {
+            debug!("queried H3DataFrame is empty - nothing to un-compact");
+            h3df
+        } else if query_options.do_uncompact {
             debug!(
                 "Un-compacting queried H3DataFrame to target_resolution {}",
                 query_options.h3_resolution
@@ -378,6 +684,68 @@ where
         Ok(out_h3df)
     }
 
+    async fn tableset_contains_cells<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+        cells: Vec<H3Cell>,
+        h3_resolution: u8,
+    ) -> Result<H3CellSet, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync,
+    {
+        if cells.is_empty() {
+            return Ok(H3CellSet::default());
+        }
+        let tableset = tableset
+            .load_tableset_from_store(self, database_name.as_ref())
+            .await?;
+
+        let (query_string, cells) = spawn_blocking(move || {
+            let cells = change_resolution(cells.as_slice(), h3_resolution)
+                .collect::<Result<Vec<_>, _>>()?;
+            TableSetQuery::TemplatedSelect(format!(
+                "select distinct {} from <[table]> where {} in <[h3indexes]>",
+                COL_NAME_H3INDEX, COL_NAME_H3INDEX
+            ))
+            .build_cell_query_string(&tableset, h3_resolution, &cells, false)
+            .map(|query_string| (query_string, cells))
+        })
+        .await??;
+
+        let df = self
+            .execute_into_dataframe(QueryInfo {
+                query: query_string,
+                database: database_name.as_ref().to_string(),
+                ..Default::default()
+            })
+            .await?;
+        let found_h3indexes: HashSet<u64> = df
+            .column(COL_NAME_H3INDEX)?
+            .u64()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let mut contained_cells = H3CellSet::default();
+        for cell in cells {
+            // the data may also be stored in the compacted form in one of the parent cells
+            let mut is_contained = found_h3indexes.contains(&cell.h3index());
+            for parent_resolution in 0..h3_resolution {
+                if is_contained {
+                    break;
+                }
+                is_contained =
+                    found_h3indexes.contains(&cell.get_parent(parent_resolution)?.h3index());
+            }
+            if is_contained {
+                contained_cells.insert(cell);
+            }
+        }
+        Ok(contained_cells)
+    }
+
     async fn tableset_stats<S, TS>(
         &mut self,
         database_name: S,
@@ -454,6 +822,86 @@ where
         df.sort_in_place(["resolution"], vec![false])?;
         Ok(df)
     }
+
+    async fn resolution_coverage_fraction<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+        h3_resolution: u8,
+    ) -> Result<f64, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync,
+    {
+        let tableset = tableset
+            .load_tableset_from_store(self, database_name.as_ref())
+            .await?;
+        let table = tableset
+            .base_tables()
+            .into_iter()
+            .find(|table| table.spec.h3_resolution == h3_resolution)
+            .ok_or_else(|| {
+                Error::H3ResolutionNotInTableSet(h3_resolution, tableset.base_resolutions())
+            })?;
+
+        let df = self
+            .execute_into_dataframe(QueryInfo {
+                query: format!(
+                    "select count(distinct {}) as num_cells from {}",
+                    COL_NAME_H3INDEX,
+                    table.to_table_name()
+                ),
+                database: database_name.as_ref().to_string(),
+                ..Default::default()
+            })
+            .await?;
+        let num_cells = df.column("num_cells")?.u64()?.get(0).unwrap_or(0);
+        Ok(num_cells as f64 / num_cells_at_resolution(h3_resolution) as f64)
+    }
+
+    async fn tableset_row_counts<S, TS>(
+        &mut self,
+        database_name: S,
+        tableset: TS,
+    ) -> Result<HashMap<String, u64>, Error>
+    where
+        S: AsRef<str> + Send + Sync,
+        TS: LoadTableSet + Send + Sync,
+    {
+        let tableset = tableset
+            .load_tableset_from_store(self, database_name.as_ref())
+            .await?;
+
+        stream::iter(tableset.tables().into_iter().map(|table| {
+            let mut client = self.clone();
+            let database_name = database_name.as_ref().to_string();
+            let table_name = table.to_table_name();
+            async move {
+                let df = client
+                    .execute_into_dataframe(QueryInfo {
+                        query: format!("select count() as num_rows from {}", table_name),
+                        database: database_name,
+                        ..Default::default()
+                    })
+                    .await?;
+                let num_rows = df.column("num_rows")?.u64()?.get(0).unwrap_or(0);
+                Ok::<_, Error>((table_name, num_rows))
+            }
+        }))
+        .buffer_unordered(DEFAULT_MAX_CONCURRENT_QUERIES)
+        .try_collect()
+        .await
+    }
+}
+
+/// the sum of the row counts returned by [`CompactedTablesStore::tableset_row_counts`]
+pub fn total_row_count(row_counts: &HashMap<String, u64>) -> u64 {
+    row_counts.values().sum()
+}
+
+/// the total number of h3 cells at the given resolution
+fn num_cells_at_resolution(h3_resolution: u8) -> u64 {
+    2 + 120 * 7u64.pow(h3_resolution as u32)
 }
 
 fn uncompact(
@@ -463,14 +911,12 @@ fn uncompact(
 ) -> Result<H3DataFrame<H3Cell>, Error> {
     // use restricted uncompacting to filter by input cells so we
     // avoid over-fetching in case of large, compacted cells.
-    let cells: H3CellSet = change_resolution(
-        cell_subset
-            .into_iter()
-            .filter(|c| c.resolution() <= target_resolution),
-        target_resolution,
-    )
-    .filter_map(|c| c.ok())
-    .collect();
+    //
+    // Input cells finer than the target resolution are brought to their parent at the target
+    // resolution as these are the cells the query fetched the data for.
+    let cells: H3CellSet = change_resolution(cell_subset.into_iter(), target_resolution)
+        .filter_map(|c| c.ok())
+        .collect();
 
     h3df.h3_uncompact_dataframe_subset(target_resolution, &cells)
         .map_err(Error::from)
@@ -511,3 +957,456 @@ fn uncompacted_counts_stmt(ts: &TableSet, database_name: &str) -> QueryInfo {
         ..Default::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use geo_types::Coord;
+    use h3ron::{H3Cell, Index};
+    use h3ron_polars::frame::H3DataFrame;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use ukis_clickhouse_arrow_grpc::mock::MockClient;
+    use ukis_clickhouse_arrow_grpc::{ArrowInterface, QueryInfo, QueryResult};
+
+    use crate::clickhouse::compacted_tables::schema::{
+        ClickhouseDataType, ColumnDefinition, CompactedTableSchema, CompactedTableSchemaBuilder,
+        SimpleColumn,
+    };
+    use crate::clickhouse::compacted_tables::tableset::find_tablesets;
+    use crate::clickhouse::compacted_tables::{
+        num_cells_at_resolution, total_row_count, uncompact, CompactedTablesStore, QueryOptions,
+        QueryPriority, Table, COL_NAME_H3INDEX,
+    };
+    use crate::Error;
+
+    #[test]
+    fn uncompact_with_finer_input_cells() {
+        let cell = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 5).unwrap();
+        let compacted_cell = cell.get_parent(4).unwrap();
+        let child_cell = cell.get_children(6).unwrap().iter().next().unwrap();
+
+        let h3df = H3DataFrame::from_dataframe(
+            DataFrame::new(vec![Series::new(
+                COL_NAME_H3INDEX,
+                &[compacted_cell.h3index()],
+            )])
+            .unwrap(),
+            COL_NAME_H3INDEX,
+        )
+        .unwrap();
+
+        // the query for the child cell fetches the data of its parents
+        let uncompacted = uncompact(h3df, vec![child_cell], 5).unwrap();
+        assert_eq!(
+            uncompacted
+                .dataframe()
+                .column(COL_NAME_H3INDEX)
+                .unwrap()
+                .u64()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Some(cell.h3index())]
+        );
+    }
+
+    #[test]
+    fn query_priority_setting() {
+        let mut query_info = QueryInfo::default();
+        QueryPriority::Normal.apply_to(&mut query_info);
+        assert!(query_info.settings.is_empty());
+
+        QueryPriority::High.apply_to(&mut query_info);
+        assert_eq!(query_info.settings.get("priority"), Some(&"1".to_string()));
+
+        QueryPriority::Low.apply_to(&mut query_info);
+        assert_eq!(query_info.settings.get("priority"), Some(&"10".to_string()));
+    }
+
+    /// wraps the [`MockClient`] to track how many column-discovery queries are running at once
+    #[derive(Clone, Default)]
+    struct ConcurrencyProbeClient {
+        inner: MockClient,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl ArrowInterface for ConcurrencyProbeClient {
+        async fn execute_query_checked(
+            &mut self,
+            q: QueryInfo,
+        ) -> Result<QueryResult, ukis_clickhouse_arrow_grpc::Error> {
+            self.inner.execute_query_checked(q).await
+        }
+
+        async fn execute_into_dataframe(
+            &mut self,
+            q: QueryInfo,
+        ) -> Result<DataFrame, ukis_clickhouse_arrow_grpc::Error> {
+            if !q.query.contains("group by name, type") {
+                return self.inner.execute_into_dataframe(q).await;
+            }
+            let in_flight = self.in_flight.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            self.max_in_flight
+                .fetch_max(in_flight, AtomicOrdering::SeqCst);
+
+            // give the other queries the chance to start
+            for _ in 0..5 {
+                tokio::task::yield_now().await;
+            }
+            let result = self.inner.execute_into_dataframe(q).await;
+            self.in_flight.fetch_sub(1, AtomicOrdering::SeqCst);
+            result
+        }
+
+        async fn insert_dataframe<S1, S2>(
+            &mut self,
+            database_name: S1,
+            table_name: S2,
+            df: DataFrame,
+        ) -> Result<(), ukis_clickhouse_arrow_grpc::Error>
+        where
+            S1: AsRef<str> + Send,
+            S2: AsRef<str> + Send,
+        {
+            self.inner
+                .insert_dataframe(database_name, table_name, df)
+                .await
+        }
+    }
+
+    fn concurrency_probe_client() -> ConcurrencyProbeClient {
+        let client = ConcurrencyProbeClient::default();
+        client
+            .inner
+            .respond_with_dataframe(
+                "select table from system.columns",
+                DataFrame::new(vec![Series::new(
+                    "table",
+                    &["elephants_05_base", "lions_05_base", "zebras_05_base"],
+                )])
+                .unwrap(),
+            )
+            .respond_with_dataframe(
+                "group by name, type",
+                DataFrame::new(vec![
+                    Series::new("name", &["observed_on"]),
+                    Series::new("type", &["DateTime"]),
+                    Series::new("c", &[1u64]),
+                ])
+                .unwrap(),
+            );
+        client
+    }
+
+    #[tokio::test]
+    async fn list_tablesets_discovers_columns_concurrently() {
+        let mut client = concurrency_probe_client();
+        let tablesets = client.list_tablesets("db").await.unwrap();
+        assert_eq!(tablesets.len(), 3);
+        for ts in tablesets.values() {
+            assert_eq!(
+                ts.columns.get("observed_on").map(String::as_str),
+                Some("DateTime")
+            );
+        }
+        assert_eq!(client.max_in_flight.load(AtomicOrdering::SeqCst), 3);
+
+        let mut client = concurrency_probe_client();
+        let tablesets = client.list_tablesets_concurrently("db", 1).await.unwrap();
+        assert_eq!(tablesets.len(), 3);
+        assert_eq!(client.max_in_flight.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn tableset_contains_cells() {
+        let tableset = find_tablesets([
+            "elephants_04_compacted",
+            "elephants_05_base",
+            "elephants_05_compacted",
+        ])
+        .remove("elephants")
+        .unwrap();
+
+        let cell_stored = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 5).unwrap();
+        let cell_stored_compacted = H3Cell::from_coordinate(Coord::from((10.1, 40.2)), 5).unwrap();
+        let cell_missing = H3Cell::from_coordinate(Coord::from((-60.1, 10.2)), 5).unwrap();
+
+        let mut client = MockClient::new();
+        client.respond_with_dataframe(
+            "select distinct h3index",
+            DataFrame::new(vec![Series::new(
+                COL_NAME_H3INDEX,
+                &[
+                    cell_stored.h3index(),
+                    cell_stored_compacted.get_parent(4).unwrap().h3index(),
+                ],
+            )])
+            .unwrap(),
+        );
+
+        let contained = client
+            .tableset_contains_cells(
+                "db",
+                tableset,
+                vec![cell_stored, cell_stored_compacted, cell_missing],
+                5,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(contained.len(), 2);
+        assert!(contained.contains(&cell_stored));
+        assert!(contained.contains(&cell_stored_compacted));
+
+        // a single query is used
+        assert_eq!(client.queries().len(), 1);
+    }
+
+    #[test]
+    fn test_num_cells_at_resolution() {
+        assert_eq!(num_cells_at_resolution(0), 122);
+        assert_eq!(num_cells_at_resolution(1), 842);
+        assert_eq!(num_cells_at_resolution(15), 569_707_381_193_162);
+    }
+
+    #[tokio::test]
+    async fn resolution_coverage_fraction() {
+        let tableset = find_tablesets(["elephants_00_base", "elephants_00_compacted"])
+            .remove("elephants")
+            .unwrap();
+
+        let mut client = MockClient::new();
+        client.respond_with_dataframe(
+            "select count(distinct h3index)",
+            DataFrame::new(vec![Series::new("num_cells", &[61u64])]).unwrap(),
+        );
+
+        let fraction = client
+            .resolution_coverage_fraction("db", tableset.clone(), 0)
+            .await
+            .unwrap();
+        assert!((fraction - 0.5).abs() < f64::EPSILON);
+        assert!(client.queries()[0].contains("from elephants_00_base"));
+
+        assert!(matches!(
+            client.resolution_coverage_fraction("db", tableset, 1).await,
+            Err(Error::H3ResolutionNotInTableSet(1, available)) if available == vec![0]
+        ));
+    }
+
+    #[tokio::test]
+    async fn tableset_row_counts() {
+        let tableset = find_tablesets([
+            "elephants_04_compacted",
+            "elephants_05_base",
+            "elephants_05_compacted",
+        ])
+        .remove("elephants")
+        .unwrap();
+
+        let mut client = MockClient::new();
+        client
+            .respond_with_dataframe(
+                "from elephants_04_compacted",
+                DataFrame::new(vec![Series::new("num_rows", &[3u64])]).unwrap(),
+            )
+            .respond_with_dataframe(
+                "from elephants_05_base",
+                DataFrame::new(vec![Series::new("num_rows", &[120u64])]).unwrap(),
+            )
+            .respond_with_dataframe(
+                "from elephants_05_compacted",
+                DataFrame::new(vec![Series::new("num_rows", &[0u64])]).unwrap(),
+            );
+
+        let row_counts = client.tableset_row_counts("db", tableset).await.unwrap();
+        assert_eq!(row_counts.len(), 3);
+        assert_eq!(row_counts["elephants_04_compacted"], 3);
+        assert_eq!(row_counts["elephants_05_base"], 120);
+        assert_eq!(row_counts["elephants_05_compacted"], 0);
+        assert_eq!(total_row_count(&row_counts), 123);
+        assert!(client
+            .queries()
+            .iter()
+            .all(|query| query.starts_with("select count() as num_rows from elephants_")));
+    }
+
+    #[tokio::test]
+    async fn drop_tableset_drops_all_tables() {
+        let table_names = [
+            "elephants_04_base",
+            "elephants_05_base",
+            "elephants_04_compacted",
+            "elephants_03_compacted",
+        ];
+        let tableset = find_tablesets(table_names).remove("elephants").unwrap();
+
+        let mut client = MockClient::new();
+        assert_eq!(client.drop_tableset("db", tableset).await.unwrap(), 4);
+
+        let mut queries = client.queries();
+        queries.sort_unstable();
+        let mut expected: Vec<_> = table_names
+            .iter()
+            .map(|table_name| format!("drop table if exists {}", table_name))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(queries, expected);
+    }
+
+    fn replicated_elephants_schema(name: &str) -> CompactedTableSchema {
+        CompactedTableSchemaBuilder::new(name)
+            .h3_base_resolutions(vec![5])
+            .on_cluster("savanna")
+            .with_replication("/clickhouse/tables/{shard}", "{replica}")
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// a client knowing the tables of the `schema`
+    fn client_with_tableset(schema: &CompactedTableSchema) -> MockClient {
+        let table_names: Vec<_> = Table::all_for_schema(schema)
+            .unwrap()
+            .iter()
+            .map(|table| table.to_table_name())
+            .collect();
+        let client = MockClient::new();
+        client
+            .respond_with_dataframe(
+                "select table from system.columns",
+                DataFrame::new(vec![Series::new("table", table_names)]).unwrap(),
+            )
+            .respond_with_dataframe(
+                "select name, type, count(*) as c",
+                DataFrame::new(vec![
+                    Series::new("name", &["elephant_count"]),
+                    Series::new("type", &["UInt32"]),
+                    Series::new("c", &[7u64]),
+                ])
+                .unwrap(),
+            );
+        client
+    }
+
+    #[tokio::test]
+    async fn copy_and_move_tableset() {
+        let schema = replicated_elephants_schema("elephants");
+        let mut client = client_with_tableset(&schema);
+
+        client
+            .move_tableset("db", &schema, "pachyderms")
+            .await
+            .unwrap();
+        let queries = client.queries();
+        let position = |query: &str| queries.iter().position(|q| q == query).unwrap();
+        let source_tables = Table::all_for_schema(&schema).unwrap();
+        let target_schema = replicated_elephants_schema("pachyderms");
+        let target_tables = Table::all_for_schema(&target_schema).unwrap();
+        let create_statements = target_schema.build_create_statements(&None).unwrap();
+        assert_eq!(create_statements.len(), source_tables.len());
+        for ((source, target), create_statement) in source_tables
+            .iter()
+            .zip(target_tables.iter())
+            .zip(create_statements.iter())
+        {
+            let create_pos = position(create_statement);
+            let insert_pos = position(&format!(
+                "insert into {} (elephant_count, h3index) select elephant_count, h3index from {}",
+                target.to_table_name(),
+                source.to_table_name()
+            ));
+            let drop_pos = position(&format!("drop table if exists {}", source.to_table_name()));
+            assert!(create_pos < insert_pos);
+            assert!(insert_pos < drop_pos);
+        }
+        // the created tables use the cluster and the replication of the schema
+        assert!(create_statements[0].contains("pachyderms_05_base ON CLUSTER savanna ("));
+        assert!(create_statements[0]
+            .contains("ReplicatedReplacingMergeTree('/clickhouse/tables/{shard}/pachyderms_05_base', '{replica}')"));
+
+        assert!(matches!(
+            client.copy_tableset("db", &schema, "elephants").await,
+            Err(Error::TableSetAlreadyExists(_))
+        ));
+        assert!(client
+            .copy_tableset("db", &schema, "no spaces")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn copy_tableset_drops_created_tables_on_error() {
+        let schema = replicated_elephants_schema("elephants");
+        let mut client = client_with_tableset(&schema);
+        client.respond_with_exception(
+            "insert into pachyderms_00_compacted",
+            "DB::Exception: Memory limit exceeded",
+        );
+
+        assert!(matches!(
+            client.copy_tableset("db", &schema, "pachyderms").await,
+            Err(Error::ClickhouseException(_))
+        ));
+        let drop_queries: Vec<_> = client
+            .queries()
+            .into_iter()
+            .filter(|query| query.starts_with("DROP TABLE"))
+            .collect();
+        assert_eq!(
+            drop_queries,
+            vec![
+                "DROP TABLE IF EXISTS pachyderms_05_base ON CLUSTER savanna".to_string(),
+                "DROP TABLE IF EXISTS pachyderms_00_compacted ON CLUSTER savanna".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_tableset_cells_empty_result() {
+        let mut tableset = find_tablesets(["elephants_04_compacted", "elephants_05_base"])
+            .remove("elephants")
+            .unwrap();
+        tableset
+            .columns
+            .insert("elephant_count".to_string(), "UInt32".to_string());
+
+        let mut client = MockClient::new();
+        client.respond_with_dataframe(
+            "select h3index, elephant_count",
+            DataFrame::new(vec![
+                Series::new(COL_NAME_H3INDEX, Vec::<u64>::new()),
+                Series::new("elephant_count", Vec::<u32>::new()),
+            ])
+            .unwrap(),
+        );
+
+        let cell = H3Cell::from_coordinate(Coord::from((-60.1, 10.2)), 5).unwrap();
+        let h3df = client
+            .query_tableset_cells(
+                "db",
+                tableset,
+                QueryOptions::new(Default::default(), vec![cell], 5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(h3df.dataframe().height(), 0);
+        assert_eq!(
+            h3df.dataframe().get_column_names(),
+            vec![COL_NAME_H3INDEX, "elephant_count"]
+        );
+    }
+}
@@ -26,6 +26,18 @@ pub enum ColumnDefinition {
     /// Aggregation only happens **within** the batch written to
     /// the tables.
     WithAggregation(SimpleColumn, AggregationMethod),
+
+    /// a column storing the intermediate state of an aggregate function, like
+    /// `AggregateFunction(avg, Float32)`.
+    ///
+    /// The states get merged by the `AggregatingMergeTree` engine. Use the `-Merge` combinator
+    /// of the function (`avgMerge(...)`) to read the final value.
+    AggregateFunction {
+        /// name of the aggregate function, like `avg` or `uniq`
+        function: String,
+        /// the type of the values being aggregated
+        inner_type: ClickhouseDataType,
+    },
 }
 
 impl ColumnDefinition {
@@ -34,6 +46,7 @@ impl ColumnDefinition {
             Self::H3Index => ClickhouseDataType::UInt64,
             Self::Simple(sc) => sc.datatype,
             Self::WithAggregation(sc, _) => sc.datatype,
+            Self::AggregateFunction { inner_type, .. } => *inner_type,
         }
     }
 
@@ -46,6 +59,50 @@ impl ColumnDefinition {
             Self::H3Index => Some(0),
             Self::Simple(sc) => sc.order_key_position,
             Self::WithAggregation(sc, _) => sc.order_key_position,
+            // states of aggregate functions can not be part of the sorting key
+            Self::AggregateFunction { .. } => None,
+        }
+    }
+
+    /// sort the column in descending order within the sorting key
+    pub fn order_descending(&self) -> bool {
+        match self {
+            Self::H3Index => false,
+            Self::Simple(sc) => sc.order_descending,
+            Self::WithAggregation(sc, _) => sc.order_descending,
+            Self::AggregateFunction { .. } => false,
+        }
+    }
+
+    /// set the position in the sorting key. Has no effect on h3index columns as these
+    /// are always the first part of the key and on aggregate function columns.
+    pub fn with_order_key_position(self, order_key_position: u8, descending: bool) -> Self {
+        match self {
+            Self::H3Index => Self::H3Index,
+            Self::Simple(sc) => {
+                Self::Simple(sc.with_order_key_position(order_key_position, descending))
+            }
+            Self::WithAggregation(sc, am) => Self::WithAggregation(
+                sc.with_order_key_position(order_key_position, descending),
+                am,
+            ),
+            Self::AggregateFunction {
+                function,
+                inner_type,
+            } => Self::AggregateFunction {
+                function,
+                inner_type,
+            },
+        }
+    }
+
+    /// comment of the column stored in ClickHouse
+    pub fn comment(&self) -> Option<&str> {
+        match self {
+            Self::H3Index => None,
+            Self::Simple(sc) => sc.comment.as_deref(),
+            Self::WithAggregation(sc, _) => sc.comment.as_deref(),
+            Self::AggregateFunction { .. } => None,
         }
     }
 
@@ -54,6 +111,7 @@ impl ColumnDefinition {
             ColumnDefinition::Simple(sc) => sc.compression_method.as_ref(),
             ColumnDefinition::H3Index => None,
             ColumnDefinition::WithAggregation(sc, _) => sc.compression_method.as_ref(),
+            ColumnDefinition::AggregateFunction { .. } => None,
         }
     }
 
@@ -62,6 +120,7 @@ impl ColumnDefinition {
             ColumnDefinition::Simple(sc) => sc.nullable,
             ColumnDefinition::H3Index => false,
             ColumnDefinition::WithAggregation(sc, _) => sc.nullable,
+            ColumnDefinition::AggregateFunction { .. } => false,
         }
     }
 
@@ -94,6 +153,21 @@ impl ValidateSchema for ColumnDefinition {
                 ));
             }
         }
+        if let Self::AggregateFunction { function, .. } = self {
+            let is_valid_function_name = function
+                .chars()
+                .next()
+                .map_or(false, |c| c.is_ascii_alphabetic())
+                && function
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_valid_function_name {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!("invalid aggregate function name: \"{}\"", function),
+                ));
+            }
+        }
         Ok(())
     }
 }
@@ -117,6 +191,15 @@ pub struct SimpleColumn {
 
     #[cfg_attr(feature = "use_serde", serde(default = "default_nullable"))]
     nullable: bool,
+
+    /// use a descending order in the sorting key. Requires the `allow_experimental_reverse_key`
+    /// MergeTree setting of ClickHouse.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    order_descending: bool,
+
+    /// comment describing the column. Visible in the `system.columns` table of ClickHouse.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    comment: Option<String>,
 }
 
 impl SimpleColumn {
@@ -131,6 +214,19 @@ impl SimpleColumn {
             order_key_position,
             compression_method,
             nullable,
+            order_descending: false,
+            comment: None,
         }
     }
+
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn with_order_key_position(mut self, order_key_position: u8, descending: bool) -> Self {
+        self.order_key_position = Some(order_key_position);
+        self.order_descending = descending;
+        self
+    }
 }
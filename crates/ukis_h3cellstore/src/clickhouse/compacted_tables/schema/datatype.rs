@@ -1,7 +1,8 @@
+use polars::prelude::{DataType, TimeUnit};
 #[cfg(feature = "use_serde")]
 use serde::{Deserialize, Serialize};
 
-use crate::Named;
+use crate::{Error, Named};
 
 /// supported subset of the clickhouse datatypes.
 ///
@@ -67,6 +68,51 @@ impl ClickhouseDataType {
             ClickhouseDataType::String => "String",
         }
     }
+
+    /// the polars datatype used for columns of this type in dataframes
+    pub fn polars_datatype(&self) -> DataType {
+        match self {
+            ClickhouseDataType::UInt8 => DataType::UInt8,
+            ClickhouseDataType::Int8 => DataType::Int8,
+            ClickhouseDataType::UInt16 => DataType::UInt16,
+            ClickhouseDataType::Int16 => DataType::Int16,
+            ClickhouseDataType::UInt32 => DataType::UInt32,
+            ClickhouseDataType::Int32 => DataType::Int32,
+            ClickhouseDataType::UInt64 => DataType::UInt64,
+            ClickhouseDataType::Int64 => DataType::Int64,
+            ClickhouseDataType::Float32 => DataType::Float32,
+            ClickhouseDataType::Float64 => DataType::Float64,
+            ClickhouseDataType::Date => DataType::Date,
+            ClickhouseDataType::DateTime | ClickhouseDataType::DateTime64 => {
+                DataType::Datetime(TimeUnit::Milliseconds, None)
+            }
+            ClickhouseDataType::String => DataType::Utf8,
+        }
+    }
+}
+
+impl TryFrom<&DataType> for ClickhouseDataType {
+    type Error = Error;
+
+    /// the clickhouse datatype to store columns of the polars datatype in
+    fn try_from(datatype: &DataType) -> Result<Self, Self::Error> {
+        Ok(match datatype {
+            DataType::UInt8 => ClickhouseDataType::UInt8,
+            DataType::Int8 => ClickhouseDataType::Int8,
+            DataType::UInt16 => ClickhouseDataType::UInt16,
+            DataType::Int16 => ClickhouseDataType::Int16,
+            DataType::UInt32 => ClickhouseDataType::UInt32,
+            DataType::Int32 => ClickhouseDataType::Int32,
+            DataType::UInt64 => ClickhouseDataType::UInt64,
+            DataType::Int64 => ClickhouseDataType::Int64,
+            DataType::Float32 => ClickhouseDataType::Float32,
+            DataType::Float64 => ClickhouseDataType::Float64,
+            DataType::Date => ClickhouseDataType::Date,
+            DataType::Datetime(_, _) => ClickhouseDataType::DateTime64,
+            DataType::Utf8 => ClickhouseDataType::String,
+            _ => return Err(Error::UnknownDatatype(datatype.to_string())),
+        })
+    }
 }
 
 impl Named for ClickhouseDataType {
@@ -77,11 +123,39 @@ impl Named for ClickhouseDataType {
 
 #[cfg(test)]
 mod tests {
+    use polars::prelude::{DataType, TimeUnit};
+
+    use crate::clickhouse::compacted_tables::schema::ClickhouseDataType;
+    use crate::Error;
+
+    #[test]
+    fn datatype_from_polars() {
+        for chdt in [
+            ClickhouseDataType::UInt8,
+            ClickhouseDataType::Int64,
+            ClickhouseDataType::Float32,
+            ClickhouseDataType::Date,
+            ClickhouseDataType::String,
+        ] {
+            assert_eq!(
+                ClickhouseDataType::try_from(&chdt.polars_datatype()).unwrap(),
+                chdt
+            );
+        }
+        assert_eq!(
+            ClickhouseDataType::try_from(&DataType::Datetime(TimeUnit::Microseconds, None))
+                .unwrap(),
+            ClickhouseDataType::DateTime64
+        );
+        assert!(matches!(
+            ClickhouseDataType::try_from(&DataType::Boolean),
+            Err(Error::UnknownDatatype(_))
+        ));
+    }
 
     #[cfg(feature = "use_serde")]
     #[test]
     fn datatype_from_str() {
-        use crate::clickhouse::compacted_tables::schema::ClickhouseDataType;
         assert_eq!(
             serde_json::from_str::<ClickhouseDataType>("\"UInt8\"").unwrap(),
             ClickhouseDataType::UInt8
@@ -9,21 +9,24 @@ use serde::{Deserialize, Serialize};
 pub use agg::AggregationMethod;
 pub use column::{ColumnDefinition, SimpleColumn};
 pub use datatype::ClickhouseDataType;
-use h3ron::H3_MAX_RESOLUTION;
+use h3ron::{H3Cell, Index, H3_MAX_RESOLUTION};
+use h3ron_polars::frame::H3DataFrame;
 use once_cell::sync::Lazy;
-pub use other::{CompressionMethod, TableEngine};
+pub use other::{CompressionMethod, ReplicationConfig, TableEngine, TtlConfig};
 pub use partitioning::{H3Partitioning, TemporalPartitioning, TemporalResolution};
+use polars::prelude::{DataFrame, DataType, Schema, Series};
 use std::collections::HashMap;
 
 use crate::clickhouse::compacted_tables::temporary_key::TemporaryKey;
 use crate::clickhouse::compacted_tables::{Table, TableSpec, COL_NAME_H3INDEX};
-use crate::Error;
+use crate::{Error, Named};
 
 pub mod agg;
 pub mod column;
 pub mod datatype;
 pub mod other;
 pub mod partitioning;
+mod reconstruct;
 
 pub trait ValidateSchema {
     fn validate(&self) -> Result<(), Error>;
@@ -36,7 +39,6 @@ pub struct CompactedTableSchema {
     table_engine: TableEngine,
     compression_method: CompressionMethod,
     pub(crate) h3_base_resolutions: Vec<u8>,
-    pub max_h3_resolution: u8,
     pub(crate) use_compaction: bool,
     temporal_resolution: TemporalResolution,
     temporal_partitioning: TemporalPartitioning,
@@ -44,6 +46,60 @@ pub struct CompactedTableSchema {
     pub(crate) columns: HashMap<String, ColumnDefinition>,
     partition_by_columns: Vec<String>,
     pub(crate) has_base_suffix: bool,
+
+    /// replication of the non-temporary tables. `None` creates non-replicated tables.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    replication: Option<ReplicationConfig>,
+
+    /// name of the cluster to run the DDL statements of the non-temporary tables on.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    on_cluster: Option<String>,
+
+    /// description of the tableset. Stored as the comment of the tables.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    description: Option<String>,
+
+    /// expiration of rows in the non-temporary tables. `None` keeps all rows.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    ttl: Option<TtlConfig>,
+
+    /// sampling expression of the tables (`SAMPLE BY`)
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    sample_by: Option<String>,
+
+    /// table-level settings of the `MergeTree` engine, like `index_granularity`.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    settings: HashMap<String, String>,
+}
+
+/// the differences between two schemas as returned by [`CompactedTableSchema::diff`].
+///
+/// All fields describe the changes from the `other` schema - typically the schema of an
+/// existing tableset - to the schema `diff` was called on.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SchemaDiff {
+    /// columns which are only part of the new schema
+    pub added_columns: Vec<String>,
+
+    /// columns which are only part of the other schema
+    pub removed_columns: Vec<String>,
+
+    /// columns with differing definitions as `(name, definition in other, definition in new)`
+    pub changed_columns: Vec<(String, ColumnDefinition, ColumnDefinition)>,
+
+    pub engine_changed: bool,
+
+    /// base resolutions which are only part of the new schema
+    pub resolutions_added: Vec<u8>,
+
+    /// base resolutions which are only part of the other schema
+    pub resolutions_removed: Vec<u8>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 #[derive(Eq)]
@@ -95,6 +151,65 @@ impl ValidateSchema for CompactedTableSchema {
         validate_table_name(type_name::<Self>(), &self.name)?;
         self.compression_method.validate()?;
         self.temporal_partitioning.validate()?;
+        if let Some(replication) = &self.replication {
+            replication.validate()?;
+        }
+        if let Some(ttl) = &self.ttl {
+            ttl.validate()?;
+            match self.columns.get(&ttl.column_name) {
+                Some(def) if def.datatype().is_temporal() => (),
+                Some(_) => {
+                    return Err(Error::SchemaValidationError(
+                        type_name::<TtlConfig>(),
+                        format!("TTL column {} is not a temporal column", ttl.column_name),
+                    ))
+                }
+                None => {
+                    return Err(Error::SchemaValidationError(
+                        type_name::<TtlConfig>(),
+                        format!("TTL column {} is not part of the schema", ttl.column_name),
+                    ))
+                }
+            }
+        }
+        if let Some(sample_by) = &self.sample_by {
+            let unknown_columns: Vec<_> = identifiers_in_expression(sample_by)
+                .into_iter()
+                .filter(|identifier| !self.columns.contains_key(*identifier))
+                .collect();
+            if !unknown_columns.is_empty() {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!(
+                        "SAMPLE BY expression references unknown columns: {}",
+                        unknown_columns.join(", ")
+                    ),
+                ));
+            }
+        }
+        for (setting_name, setting_value) in self.settings.iter() {
+            if !RE_VALID_NAME.is_match(setting_name) || setting_value.trim().is_empty() {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!(
+                        "invalid table setting: \"{}\" = \"{}\"",
+                        setting_name, setting_value
+                    ),
+                ));
+            }
+        }
+        if let Some(cluster_name) = &self.on_cluster {
+            if !RE_VALID_NAME.is_match(cluster_name) {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!("invalid cluster name: \"{}\"", cluster_name),
+                ));
+            }
+        }
+
+        for def in self.columns.values() {
+            def.validate()?;
+        }
 
         // a h3index column must exist
         self.h3index_column()?;
@@ -115,6 +230,68 @@ impl ValidateSchema for CompactedTableSchema {
                     ),
                 ));
             }
+
+            // clickhouse silently ignores non-numeric columns in the sum
+            let non_numeric_columns: Vec<_> = sum_columns
+                .iter()
+                .filter(|sum_column| {
+                    self.columns
+                        .get(*sum_column)
+                        .map(|def| !def.datatype().is_number())
+                        .unwrap_or(false)
+                })
+                .cloned()
+                .collect();
+            if !non_numeric_columns.is_empty() {
+                return Err(Error::SchemaValidationError(
+                    type_name::<TableEngine>(),
+                    format!(
+                        "SummingMergeTree engine can only sum numeric columns. Non-numeric columns: {}",
+                        non_numeric_columns.join(", ")
+                    ),
+                ));
+            }
+        }
+
+        // without any aggregate function columns the engine behaves like a plain MergeTree
+        if self.table_engine == TableEngine::AggregatingMergeTree
+            && !self
+                .columns
+                .values()
+                .any(|def| matches!(def, ColumnDefinition::AggregateFunction { .. }))
+        {
+            return Err(Error::SchemaValidationError(
+                type_name::<TableEngine>(),
+                "AggregatingMergeTree engine requires at least one AggregateFunction column"
+                    .to_string(),
+            ));
+        }
+
+        if let TableEngine::ReplacingMergeTree(Some(version_column)) = &self.table_engine {
+            match self.columns.get(version_column) {
+                Some(def)
+                    if !def.nullable()
+                        && (def.datatype().is_unsigned_integer()
+                            || def.datatype().is_temporal()) => {}
+                Some(_) => {
+                    return Err(Error::SchemaValidationError(
+                        type_name::<TableEngine>(),
+                        format!(
+                            "the version column {} of the ReplacingMergeTree engine must be a non-nullable unsigned integer or temporal column",
+                            version_column
+                        ),
+                    ))
+                }
+                None => {
+                    return Err(Error::SchemaValidationError(
+                        type_name::<TableEngine>(),
+                        format!(
+                            "ReplacingMergeTree engine is missing the version column {}",
+                            version_column
+                        ),
+                    ))
+                }
+            }
         }
 
         // validate h3 resolutions
@@ -126,6 +303,12 @@ impl ValidateSchema for CompactedTableSchema {
             ));
         }
 
+        for resolution_metadata in self.get_resolution_metadata()? {
+            self.build_table(&resolution_metadata, &None)
+                .spec
+                .is_valid()?;
+        }
+
         // a useful partitioning can be created
         self.partition_by_expressions()?;
 
@@ -135,9 +318,9 @@ impl ValidateSchema for CompactedTableSchema {
 
 // validation does not include reserved SQL keywords, but Clickhouse will fail happily when
 // encountering them as a table name anyways.
-static RE_VALID_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z].[_a-zA-Z_0-9]+$").unwrap());
+static RE_VALID_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z_0-9]*$").unwrap());
 
-fn validate_table_name(location: &'static str, name: &str) -> Result<(), Error> {
+pub(crate) fn validate_table_name(location: &'static str, name: &str) -> Result<(), Error> {
     if RE_VALID_NAME.is_match(name) {
         Ok(())
     } else {
@@ -159,8 +342,9 @@ impl CompactedTableSchema {
             spec: TableSpec {
                 h3_resolution: resolution_metadata.h3_resolution,
                 is_compacted: resolution_metadata.is_compacted,
-                temporary_key: temporary_key.as_ref().map(|tk| tk.to_string()),
-                has_base_suffix: self.has_base_suffix,
+                temporary_key: temporary_key.as_ref().map(|tk| tk.to_table_suffix()),
+                // the suffix can only be omitted for the base tables
+                has_base_suffix: self.has_base_suffix || resolution_metadata.is_compacted,
             },
         }
     }
@@ -198,6 +382,67 @@ impl CompactedTableSchema {
             .collect()
     }
 
+    /// check if the `SummingMergeTree` engine sums up the values of the column when merging rows.
+    ///
+    /// Without an explicit list of columns, the engine sums all numeric columns which are not
+    /// part of the sorting key.
+    fn is_summed_by_engine(&self, column_name: &str, def: &ColumnDefinition) -> bool {
+        match &self.table_engine {
+            TableEngine::SummingMergeTree(sum_columns) if sum_columns.is_empty() => {
+                def.datatype().is_number()
+                    && self.simple_aggregate_function(def).is_none()
+                    && !self
+                        .order_by_column_names()
+                        .iter()
+                        .any(|key_column| key_column == column_name)
+            }
+            TableEngine::SummingMergeTree(sum_columns) => sum_columns
+                .iter()
+                .any(|sum_column| sum_column == column_name),
+            _ => false,
+        }
+    }
+
+    /// the function of the `SimpleAggregateFunction` type `Max` and `Min` columns get created with.
+    ///
+    /// Merging rows would otherwise sum up or pick arbitrary values of these columns.
+    fn simple_aggregate_function(&self, def: &ColumnDefinition) -> Option<&'static str> {
+        match (&self.table_engine, def) {
+            (TableEngine::ReplacingMergeTree(_), _) => None,
+            (
+                _,
+                ColumnDefinition::WithAggregation(
+                    _,
+                    agg_method @ (AggregationMethod::Max | AggregationMethod::Min),
+                ),
+            ) => Some(agg_method.name()),
+            _ => None,
+        }
+    }
+
+    /// the type of a column as used in the DDL statements
+    fn column_sql_type(&self, def: &ColumnDefinition) -> String {
+        match self.simple_aggregate_function(def) {
+            Some(function) => format!(
+                "SimpleAggregateFunction({}, {})",
+                function,
+                column_sql_type(def)
+            ),
+            None => column_sql_type(def),
+        }
+    }
+
+    /// expressions of the sorting key of the table
+    fn order_by_expressions(&self) -> Vec<String> {
+        self.order_by_column_names()
+            .into_iter()
+            .map(|column_name| match self.columns.get(&column_name) {
+                Some(def) if def.order_descending() => format!("{} DESC", column_name),
+                _ => column_name,
+            })
+            .collect()
+    }
+
     fn get_column_definition(&self, column_name: &str) -> Result<ColumnDefinition, Error> {
         match self.columns.get(column_name) {
             Some(def) => Ok(def.clone()),
@@ -210,6 +455,31 @@ impl CompactedTableSchema {
         }
     }
 
+    /// the finest resolution of the base tables
+    pub fn max_h3_resolution(&self) -> Result<u8, Error> {
+        self.h3_base_resolutions
+            .iter()
+            .max()
+            .copied()
+            .ok_or_else(|| self.no_base_resolutions_error())
+    }
+
+    /// the coarsest resolution of the base tables
+    pub fn min_h3_resolution(&self) -> Result<u8, Error> {
+        self.h3_base_resolutions
+            .iter()
+            .min()
+            .copied()
+            .ok_or_else(|| self.no_base_resolutions_error())
+    }
+
+    fn no_base_resolutions_error(&self) -> Error {
+        Error::SchemaValidationError(
+            type_name::<Self>(),
+            format!("schema {} defines no h3 base resolutions", self.name),
+        )
+    }
+
     pub fn h3index_column(&self) -> Result<(String, ColumnDefinition), Error> {
         let def = self.get_column_definition(COL_NAME_H3INDEX)?;
 
@@ -226,11 +496,31 @@ impl CompactedTableSchema {
         }
     }
 
+    /// an empty dataframe containing all columns of the schema using the matching polars datatypes.
+    ///
+    /// Can be used as a template to build dataframes to insert into the tables of this schema.
+    pub fn to_insert_sample(&self) -> Result<H3DataFrame<H3Cell>, Error> {
+        let (h3index_col_name, _) = self.h3index_column()?;
+        let series: Vec<_> = self
+            .columns
+            .iter()
+            // the h3index column first, all others ordered by name
+            .sorted_by_key(|(col_name, _)| (col_name.as_str() != COL_NAME_H3INDEX, *col_name))
+            .map(|(col_name, def)| Series::new_empty(col_name, &def.datatype().polars_datatype()))
+            .collect();
+        Ok(H3DataFrame::from_dataframe(
+            DataFrame::new(series)?,
+            h3index_col_name,
+        )?)
+    }
+
     /// generate a single partition expression for a single column
     fn partition_by_expression(&self, column_name: &str, def: &ColumnDefinition) -> String {
         match def {
             ColumnDefinition::H3Index => self.h3_partitioning.sql_expression(column_name),
-            ColumnDefinition::Simple(_) | ColumnDefinition::WithAggregation(_, _) => {
+            ColumnDefinition::Simple(_)
+            | ColumnDefinition::WithAggregation(_, _)
+            | ColumnDefinition::AggregateFunction { .. } => {
                 if def.datatype().is_temporal() {
                     self.temporal_partitioning.sql_expression(column_name)
                 } else {
@@ -283,12 +573,7 @@ impl CompactedTableSchema {
 
     pub(crate) fn get_resolution_metadata(&self) -> Result<Vec<ResolutionMetadata>, Error> {
         let compacted_resolutions: Vec<_> = if self.use_compaction {
-            let max_res = *self
-                .h3_base_resolutions
-                .iter()
-                .max()
-                .ok_or(Error::MixedH3Resolutions)?; // TODO: better error
-            (0..=max_res)
+            (0..=self.max_h3_resolution()?)
                 .map(|r| ResolutionMetadata::new(r, true))
                 .collect()
         } else {
@@ -303,52 +588,96 @@ impl CompactedTableSchema {
             .collect())
     }
 
-    fn build_create_statement(&self, table: &Table) -> Result<String, Error> {
+    pub(crate) fn build_create_statement(&self, table: &Table) -> Result<String, Error> {
         let partition_by = if table.spec.temporary_key.is_none() {
             // partitioning is only relevant for non-temporary tables
             Some(self.partition_by_expressions()?.join(", "))
         } else {
             None
         };
-        let order_by = self.order_by_column_names().join(", ");
-        let engine = match &self.table_engine {
-            TableEngine::ReplacingMergeTree => "ReplacingMergeTree".to_string(),
-            TableEngine::SummingMergeTree(smt_columns) => {
-                format!("SummingMergeTree({})", smt_columns.join(", "))
-            }
-            TableEngine::AggregatingMergeTree => "AggregatingMergeTree".to_string(),
+        let order_by = self.order_by_expressions().join(", ");
+        let table_name = table.to_table_name();
+
+        // rows of the temporary tables must not expire during the insert
+        let ttl_clause = match &self.ttl {
+            Some(ttl) if !table.spec.is_temporary() => format!(" TTL {}", ttl.sql_expression()),
+            _ => "".to_string(),
+        };
+
+        // temporary tables are only used locally during inserts, so there is no need to replicate them
+        let replication = if table.spec.is_temporary() {
+            None
+        } else {
+            self.replication.as_ref()
         };
-        let default_codec = codec_string(&self.compression_method);
+        let engine = self.table_engine.sql_expression(replication, &table_name);
         let columns = &self
             .columns
             .iter()
             .sorted_by(|a, b| Ord::cmp(a.0, b.0)) // order to make the SQL comparable
-            .map(|(col_name, def)| {
-                let col_codec = def.compression_method().map(codec_string);
-                let col_dtype = if def.nullable() {
-                    format!("Nullable({})", def.datatype().sql_type_name())
-                } else {
-                    def.datatype().sql_type_name().to_string()
-                };
-                format!(
-                    " {} {} CODEC({})",
-                    col_name,
-                    col_dtype,
-                    col_codec.unwrap_or_else(|| default_codec.clone())
-                )
-            })
+            .map(|(col_name, def)| format!(" {}", self.column_sql(col_name, def)))
             .join(",\n");
 
+        let settings_clause = if self.settings.is_empty() {
+            "".to_string()
+        } else {
+            format!(
+                " SETTINGS {}",
+                self.settings
+                    .iter()
+                    .sorted_by(|a, b| Ord::cmp(a.0, b.0))
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .join(", ")
+            )
+        };
+
         Ok(format!(
-            "CREATE TABLE IF NOT EXISTS {} ( {} ) ENGINE {} {} ORDER BY ({});",
-            table.to_table_name(),
+            "CREATE TABLE IF NOT EXISTS {}{} ( {} ) ENGINE {} {} ORDER BY ({}){}{}{}{};",
+            table_name,
+            self.on_cluster_clause(table),
             columns,
             engine,
             partition_by.map_or_else(|| "".to_string(), |pb| format!("PARTITION BY ({})", pb)),
-            order_by
+            order_by,
+            self.sample_by
+                .as_ref()
+                .map_or_else(|| "".to_string(), |sb| format!(" SAMPLE BY ({})", sb)),
+            ttl_clause,
+            settings_clause,
+            comment_clause(self.description.as_deref())
         ))
     }
 
+    /// the definition of a column as used in `CREATE TABLE` and `ALTER TABLE` statements
+    fn column_sql(&self, col_name: &str, def: &ColumnDefinition) -> String {
+        format!(
+            "{} {}{} CODEC({})",
+            col_name,
+            self.column_sql_type(def),
+            comment_clause(def.comment()),
+            codec_string(def.compression_method().unwrap_or(&self.compression_method))
+        )
+    }
+
+    /// the `DROP TABLE` statement for a table of this schema
+    pub(crate) fn build_drop_statement(&self, table: &Table) -> String {
+        format!(
+            "DROP TABLE IF EXISTS {}{}",
+            table.to_table_name(),
+            self.on_cluster_clause(table)
+        )
+    }
+
+    /// temporary tables are only used locally during inserts, so they are never created on the cluster
+    fn on_cluster_clause(&self, table: &Table) -> String {
+        match &self.on_cluster {
+            Some(cluster_name) if !table.spec.is_temporary() => {
+                format!(" ON CLUSTER {}", cluster_name)
+            }
+            _ => "".to_string(),
+        }
+    }
+
     pub fn build_create_statements(
         &self,
         temporary_key: &Option<TemporaryKey>,
@@ -362,6 +691,207 @@ impl CompactedTableSchema {
             .collect::<Result<Vec<String>, Error>>()
     }
 
+    /// compare this schema to the `other` schema.
+    ///
+    /// The `added_columns` of the diff are the columns [`Self::alter_add_column_statements`]
+    /// adds to the tables of the `other` schema. All fields of the diff are sorted.
+    pub fn diff(&self, other: &CompactedTableSchema) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        for (col_name, def) in self.columns.iter().sorted_by(|a, b| Ord::cmp(a.0, b.0)) {
+            match other.columns.get(col_name) {
+                Some(other_def) if other_def != def => {
+                    diff.changed_columns
+                        .push((col_name.clone(), other_def.clone(), def.clone()));
+                }
+                Some(_) => (),
+                None => diff.added_columns.push(col_name.clone()),
+            }
+        }
+        diff.removed_columns = other
+            .columns
+            .keys()
+            .filter(|col_name| !self.columns.contains_key(*col_name))
+            .sorted()
+            .cloned()
+            .collect();
+        diff.engine_changed = self.table_engine != other.table_engine;
+        diff.resolutions_added = self
+            .h3_base_resolutions
+            .iter()
+            .filter(|r| !other.h3_base_resolutions.contains(r))
+            .sorted()
+            .copied()
+            .collect();
+        diff.resolutions_removed = other
+            .h3_base_resolutions
+            .iter()
+            .filter(|r| !self.h3_base_resolutions.contains(r))
+            .sorted()
+            .copied()
+            .collect();
+        diff
+    }
+
+    /// build the statements to add the columns of this schema missing in the `existing` schema
+    /// to all tables of the tableset.
+    ///
+    /// Columns only present in the `existing` schema are left untouched. Fails with
+    /// [`Error::IncompatibleDatatype`] when a column exists in both schemas with different
+    /// types.
+    pub fn alter_add_column_statements(
+        &self,
+        existing: &CompactedTableSchema,
+    ) -> Result<Vec<String>, Error> {
+        let mut new_columns = vec![];
+        for (col_name, def) in self.columns.iter().sorted_by(|a, b| Ord::cmp(a.0, b.0)) {
+            match existing.columns.get(col_name) {
+                Some(existing_def) => {
+                    let existing_type = existing.column_sql_type(existing_def);
+                    let new_type = self.column_sql_type(def);
+                    if existing_type != new_type {
+                        return Err(Error::IncompatibleDatatype(
+                            col_name.clone(),
+                            existing_type,
+                            new_type,
+                        ));
+                    }
+                }
+                None => new_columns.push(self.column_sql(col_name, def)),
+            }
+        }
+        if new_columns.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let statements = Table::all_for_schema(self)?
+            .iter()
+            .map(|table| {
+                format!(
+                    "ALTER TABLE {}{} {};",
+                    table.to_table_name(),
+                    self.on_cluster_clause(table),
+                    new_columns
+                        .iter()
+                        .map(|column_sql| format!("ADD COLUMN IF NOT EXISTS {}", column_sql))
+                        .join(", ")
+                )
+            })
+            .collect();
+        Ok(statements)
+    }
+
+    /// build the statements for materialized views aggregating the rows inserted into a base table
+    /// into the base table of the next coarser resolution. Not to be combined with the `Inserter`.
+    /// Fails for `Average` and `SetNullOnConflict` columns, which can not be merged from partial rows.
+    pub fn create_materialized_view_statements(&self) -> Result<Vec<String>, Error> {
+        self.validate_materialized_view_aggregations()?;
+        let alias_source = "src";
+        let resolutions: Vec<_> = self.h3_base_resolutions.iter().sorted().copied().collect();
+
+        let mut statements = Vec::with_capacity(resolutions.len().saturating_sub(1));
+        for window in resolutions.windows(2) {
+            let target_resolution = window[0];
+            let source_resolution = window[1];
+            let source_table =
+                self.build_table(&ResolutionMetadata::new(source_resolution, false), &None);
+            let target_table =
+                self.build_table(&ResolutionMetadata::new(target_resolution, false), &None);
+            let target_table_name = target_table.to_table_name();
+
+            let parent_expr = format!(
+                "h3ToParent({}.{}, {})",
+                alias_source, COL_NAME_H3INDEX, target_resolution
+            );
+            let mut select_exprs = vec![format!("{} AS {}", parent_expr, COL_NAME_H3INDEX)];
+            let mut group_by_exprs = vec![parent_expr.clone()];
+            for (col_name, def) in self
+                .columns
+                .iter()
+                .filter(|(col_name, _)| col_name.as_str() != COL_NAME_H3INDEX)
+                .sorted_by(|a, b| Ord::cmp(a.0, b.0))
+            {
+                let source_column = format!("{}.{}", alias_source, col_name);
+                let column_expr = match def {
+                    ColumnDefinition::WithAggregation(_, AggregationMethod::RelativeToCellArea) => {
+                        // like the Inserter, as pentagons have fewer children
+                        format!(
+                            "(sum({}) / length(h3ToChildren({}, {})))",
+                            source_column, parent_expr, source_resolution
+                        )
+                    }
+                    ColumnDefinition::WithAggregation(
+                        _,
+                        agg_method @ (AggregationMethod::Max | AggregationMethod::Min),
+                    ) => format!("{}({})", agg_method.name(), source_column),
+                    // all other aggregation methods besides `Sum` have been rejected by the validation
+                    ColumnDefinition::WithAggregation(_, _) => format!("sum({})", source_column),
+                    ColumnDefinition::AggregateFunction { function, .. } => {
+                        format!("{}MergeState({})", function, source_column)
+                    }
+                    _ => {
+                        group_by_exprs.push(source_column.clone());
+                        source_column
+                    }
+                };
+                select_exprs.push(format!("{} AS {}", column_expr, col_name));
+            }
+
+            statements.push(format!(
+                "CREATE MATERIALIZED VIEW IF NOT EXISTS {}_to_{:02}_mv{} TO {} AS SELECT {} FROM {} AS {} GROUP BY {};",
+                source_table.to_table_name(),
+                target_resolution,
+                self.on_cluster_clause(&target_table),
+                target_table_name,
+                select_exprs.join(", "),
+                source_table.to_table_name(),
+                alias_source,
+                group_by_exprs.join(", ")
+            ));
+        }
+        Ok(statements)
+    }
+
+    /// check that the engine merges the partial rows written by the materialized views correctly
+    fn validate_materialized_view_aggregations(&self) -> Result<(), Error> {
+        if let TableEngine::ReplacingMergeTree(_) = &self.table_engine {
+            return Err(Error::SchemaValidationError(
+                type_name::<TableEngine>(),
+                "materialized views require the SummingMergeTree or AggregatingMergeTree engines to merge the aggregated rows".to_string(),
+            ));
+        }
+        for (col_name, def) in self.columns.iter().sorted_by(|a, b| Ord::cmp(a.0, b.0)) {
+            if let ColumnDefinition::WithAggregation(_, agg_method) = def {
+                match agg_method {
+                    AggregationMethod::Sum | AggregationMethod::RelativeToCellArea => {
+                        if !self.is_summed_by_engine(col_name, def) {
+                            return Err(Error::SchemaValidationError(
+                                type_name::<Self>(),
+                                format!(
+                                    "column {} using the {} aggregation must be summed by the SummingMergeTree engine to be used in materialized views",
+                                    col_name,
+                                    agg_method.name()
+                                ),
+                            ));
+                        }
+                    }
+                    // merged by their SimpleAggregateFunction type
+                    AggregationMethod::Max | AggregationMethod::Min => (),
+                    AggregationMethod::Average | AggregationMethod::SetNullOnConflict => {
+                        return Err(Error::SchemaValidationError(
+                            type_name::<Self>(),
+                            format!(
+                                "the {} aggregation of column {} can not be merged from partial rows. Use an AggregateFunction column for materialized views",
+                                agg_method.name(),
+                                col_name
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn build_drop_statements(
         &self,
         temporary_key: &Option<TemporaryKey>,
@@ -371,10 +901,120 @@ impl CompactedTableSchema {
             .iter()
             .map(|resolution_metadata| {
                 let table = self.build_table(resolution_metadata, temporary_key);
-                format!("drop table if exists {}", table.to_table_name())
+                format!(
+                    "drop table if exists {}{}",
+                    table.to_table_name(),
+                    self.on_cluster_clause(&table)
+                )
             })
             .collect::<Vec<String>>())
     }
+
+    /// build a statement to create a view combining the base tables of all resolutions.
+    ///
+    /// The rows of the `SummingMergeTree` and `AggregatingMergeTree` engines get merged in the
+    /// background, so the view aggregates the rows of each table by `h3index`. Columns using
+    /// the `Sum` aggregation method or being summed by the engine get summed up, the states of
+    /// aggregate function columns get merged, `Max` and `Min` columns use their aggregation and all
+    /// other columns use `any()`.
+    pub fn create_view_statement(&self, view_name: &str) -> Result<String, Error> {
+        validate_table_name(type_name::<Self>(), view_name)?;
+        if let TableEngine::ReplacingMergeTree(_) = &self.table_engine {
+            return Err(Error::SchemaValidationError(
+                type_name::<TableEngine>(),
+                "views can only be created for tables using the SummingMergeTree or AggregatingMergeTree engines".to_string()
+            ));
+        }
+
+        let column_exprs = std::iter::once(COL_NAME_H3INDEX.to_string())
+            .chain(
+                self.columns
+                    .iter()
+                    .filter(|(col_name, _)| col_name.as_str() != COL_NAME_H3INDEX)
+                    .sorted_by(|a, b| Ord::cmp(a.0, b.0))
+                    .map(|(col_name, def)| {
+                        let is_summed = self.is_summed_by_engine(col_name, def)
+                            || matches!(
+                                def,
+                                ColumnDefinition::WithAggregation(_, AggregationMethod::Sum)
+                            );
+                        let function = match (def, self.simple_aggregate_function(def)) {
+                            (ColumnDefinition::AggregateFunction { function, .. }, _) => {
+                                format!("{}Merge", function)
+                            }
+                            (_, Some(function)) => function.to_string(),
+                            _ if is_summed => "sum".to_string(),
+                            _ => "any".to_string(),
+                        };
+                        format!("{}({}) as {}", function, col_name, col_name)
+                    }),
+            )
+            .join(", ");
+
+        let selects = self
+            .h3_base_resolutions
+            .iter()
+            .sorted()
+            .map(|h3_resolution| {
+                format!(
+                    "select {} from {} group by {}",
+                    column_exprs,
+                    self.build_table(&ResolutionMetadata::new(*h3_resolution, false), &None)
+                        .to_table_name(),
+                    COL_NAME_H3INDEX
+                )
+            })
+            .join(" union all ");
+        Ok(format!(
+            "CREATE VIEW IF NOT EXISTS {} AS {}",
+            view_name, selects
+        ))
+    }
+}
+
+/// ` COMMENT '...'` clause for the DDL statements
+fn comment_clause(comment: Option<&str>) -> String {
+    comment.map_or_else(
+        || "".to_string(),
+        |comment| {
+            format!(
+                " COMMENT '{}'",
+                comment.replace('\\', "\\\\").replace('\'', "\\'")
+            )
+        },
+    )
+}
+
+static RE_IDENTIFIER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[a-zA-Z_][a-zA-Z_0-9]*\s*(\()?").unwrap());
+
+/// the identifiers referenced in an SQL expression, excluding the names of the called functions
+fn identifiers_in_expression(expression: &str) -> Vec<&str> {
+    RE_IDENTIFIER
+        .captures_iter(expression)
+        .filter(|captures| captures.get(1).is_none())
+        .filter_map(|captures| captures.get(0))
+        .map(|mtch| mtch.as_str().trim_end())
+        .unique()
+        .collect()
+}
+
+fn column_sql_type(def: &ColumnDefinition) -> String {
+    if let ColumnDefinition::AggregateFunction {
+        function,
+        inner_type,
+    } = def
+    {
+        format!(
+            "AggregateFunction({}, {})",
+            function,
+            inner_type.sql_type_name()
+        )
+    } else if def.nullable() {
+        format!("Nullable({})", def.datatype().sql_type_name())
+    } else {
+        def.datatype().sql_type_name().to_string()
+    }
 }
 
 fn codec_string(compression_method: &CompressionMethod) -> String {
@@ -404,7 +1044,6 @@ fn ordered_h3_resolutions(h3res_slice: &[u8]) -> Result<Vec<u8>, Error> {
 #[derive(Clone)]
 pub struct CompactedTableSchemaBuilder {
     schema: CompactedTableSchema,
-    use_compaction: bool,
 }
 
 impl CompactedTableSchemaBuilder {
@@ -417,7 +1056,6 @@ impl CompactedTableSchemaBuilder {
                 table_engine: Default::default(),
                 compression_method: Default::default(),
                 h3_base_resolutions: vec![],
-                max_h3_resolution: 0,
                 use_compaction: true,
                 temporal_resolution: Default::default(),
                 temporal_partitioning: Default::default(),
@@ -425,11 +1063,85 @@ impl CompactedTableSchemaBuilder {
                 columns,
                 has_base_suffix: true,
                 h3_partitioning: Default::default(),
+                replication: None,
+                on_cluster: None,
+                description: None,
+                ttl: None,
+                sample_by: None,
+                settings: Default::default(),
             },
-            use_compaction: true,
         }
     }
 
+    /// create a builder with the columns of the given dataframe.
+    ///
+    /// The cells of the h3index column must all be of the same resolution, which is used as the
+    /// only base resolution. Columns containing null values are defined as nullable. Dataframes with
+    /// datetime columns get partitioned by month. All other settings are left at their defaults and
+    /// can be changed using the other methods of the builder.
+    pub fn infer_from_dataframe(
+        table_name: &str,
+        h3df: &H3DataFrame<H3Cell>,
+    ) -> Result<Self, Error> {
+        let df = h3df.dataframe();
+        let mut h3_resolution = None;
+        for h3index in df.column(h3df.h3index_column_name())?.u64()?.into_iter() {
+            let h3index = h3index.ok_or(Error::MissingIndexValue)?;
+            let cell_resolution = H3Cell::try_from(h3index)
+                .map_err(|_| Error::InvalidH3Index(h3index))?
+                .resolution();
+            match h3_resolution {
+                None => h3_resolution = Some(cell_resolution),
+                Some(r) if r != cell_resolution => return Err(Error::MixedH3Resolutions),
+                _ => (),
+            }
+        }
+        let h3_resolution = h3_resolution.ok_or(Error::EmptyCells)?;
+
+        let mut builder = Self::new(table_name).h3_base_resolutions(vec![h3_resolution]);
+        for series in df.get_columns() {
+            if series.name() == h3df.h3index_column_name() {
+                continue;
+            }
+            let datatype = ClickhouseDataType::try_from(series.dtype())?;
+            if matches!(series.dtype(), DataType::Datetime(_, _)) {
+                builder = builder.temporal_partitioning(TemporalPartitioning::Months(1));
+            }
+            builder = builder.add_column(
+                series.name(),
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    datatype,
+                    None,
+                    None,
+                    series.null_count() > 0,
+                )),
+            );
+        }
+        Ok(builder)
+    }
+
+    /// add the columns of a polars schema, for example the schema of a parquet file.
+    ///
+    /// The h3index column and the columns listed in `exclude` are skipped. All columns are
+    /// added as non-nullable columns without aggregation.
+    pub fn add_columns_from_polars_schema(
+        mut self,
+        schema: &Schema,
+        exclude: &[&str],
+    ) -> Result<Self, Error> {
+        for (column_name, dtype) in schema.iter() {
+            if column_name.as_str() == COL_NAME_H3INDEX || exclude.contains(&column_name.as_str()) {
+                continue;
+            }
+            let datatype = ClickhouseDataType::try_from(dtype)?;
+            self = self.add_column(
+                column_name.as_str(),
+                ColumnDefinition::Simple(SimpleColumn::new(datatype, None, None, false)),
+            );
+        }
+        Ok(self)
+    }
+
     pub fn table_engine(mut self, table_engine: TableEngine) -> Self {
         self.schema.table_engine = table_engine;
         self
@@ -441,18 +1153,36 @@ impl CompactedTableSchemaBuilder {
     }
 
     pub fn h3_base_resolutions(mut self, h3res: Vec<u8>) -> Self {
-        if !h3res.is_empty() {
-            self.schema.max_h3_resolution = *(h3res
-                .iter()
-                .max()
-                .expect("no resolutions to ge max res from"));
-        }
         self.schema.h3_base_resolutions = h3res;
         self
     }
 
+    /// use all resolutions from `min_res` to `max_res` (inclusive) as base resolutions.
+    ///
+    /// Replaces previously set base resolutions. Resolutions above 15 fail on `build`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `min_res` is greater than `max_res`.
+    pub fn h3_resolution_range(self, min_res: u8, max_res: u8) -> Self {
+        assert!(
+            min_res <= max_res,
+            "min_res ({}) of the h3 resolution range must not be greater than max_res ({})",
+            min_res,
+            max_res
+        );
+        self.h3_base_resolutions((min_res..=max_res).collect())
+    }
+
     pub fn use_compacted_resolutions(mut self, use_compaction: bool) -> Self {
-        self.use_compaction = use_compaction;
+        self.schema.use_compaction = use_compaction;
+        self
+    }
+
+    /// name the base tables without the `_base` suffix, for example `dataset_05` instead
+    /// of `dataset_05_base`. The compacted tables keep their `_compacted` suffix.
+    pub fn no_base_suffix(mut self) -> Self {
+        self.schema.has_base_suffix = false;
         self
     }
 
@@ -479,40 +1209,263 @@ impl CompactedTableSchemaBuilder {
         self
     }
 
-    pub fn partition_by(mut self, columns: Vec<String>) -> Self {
-        self.schema.partition_by_columns = columns;
+    /// description of the tableset. Gets stored as the comment of the tables.
+    pub fn description(mut self, description: &str) -> Self {
+        self.schema.description = Some(description.to_string());
         self
     }
 
-    pub fn build(self) -> Result<CompactedTableSchema, Error> {
-        self.schema.validate()?;
-        Ok(self.schema)
+    /// add a column at the given position of the sorting key (`ORDER BY`) of the tables.
+    ///
+    /// The h3index column is always the first part of the sorting key. The position is a `u8`
+    /// like the `order_key_position` of [`SimpleColumn::new`].
+    pub fn add_column_ordered(
+        self,
+        column_name: &str,
+        def: ColumnDefinition,
+        order_key_position: u8,
+    ) -> Self {
+        self.add_column(
+            column_name,
+            def.with_order_key_position(order_key_position, false),
+        )
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::clickhouse::compacted_tables::schema::{
-        validate_table_name, AggregationMethod, ClickhouseDataType, ColumnDefinition,
-        CompactedTableSchema, CompactedTableSchemaBuilder, CompressionMethod, ResolutionMetadata,
-        SimpleColumn, TemporalPartitioning,
-    };
+    /// add a column at the given position of the sorting key (`ORDER BY`) of the tables
+    /// sorted in descending order.
+    ///
+    /// Descending sorting keys require the `allow_experimental_reverse_key` MergeTree setting.
+    pub fn add_column_desc(
+        self,
+        column_name: &str,
+        def: ColumnDefinition,
+        order_key_position: u8,
+    ) -> Self {
+        self.add_column(
+            column_name,
+            def.with_order_key_position(order_key_position, true),
+        )
+    }
 
-    #[test]
-    fn test_validate_table_name() {
-        assert!(validate_table_name("unittest", "").is_err());
-        assert!(validate_table_name("unittest", " test").is_err());
-        assert!(validate_table_name("unittest", "4test").is_err());
-        assert!(validate_table_name("unittest", "something").is_ok());
-        assert!(validate_table_name("unittest", "some_thing").is_ok());
+    /// use the `Replicated*MergeTree` variant of the table engine for all non-temporary tables.
+    ///
+    /// The `zookeeper_path` gets the table name appended when it does not contain the `{table}` macro.
+    pub fn with_replication(mut self, zookeeper_path: &str, replica_name: &str) -> Self {
+        self.schema.replication = Some(ReplicationConfig::new(zookeeper_path, replica_name));
+        self
     }
 
-    fn data_okavango_delta() -> CompactedTableSchema {
-        CompactedTableSchemaBuilder::new("okavango_delta")
-            .h3_base_resolutions(vec![1, 2, 3, 4, 5])
-            .temporal_partitioning(TemporalPartitioning::Months(1))
-            .add_column(
-                "elephant_density",
+    /// delete rows once the value of the temporal column `column_name` is older than
+    /// `interval_days` days.
+    pub fn ttl(mut self, column_name: &str, interval_days: u32) -> Self {
+        self.schema.ttl = Some(TtlConfig::new(column_name, interval_days));
+        self
+    }
+
+    /// sampling expression of the tables used by `SAMPLE` queries. ClickHouse requires the
+    /// expression to be part of the sorting key, for example the `h3index` column.
+    pub fn sample_by(mut self, expression: &str) -> Self {
+        self.schema.sample_by = Some(expression.to_string());
+        self
+    }
+
+    /// set a table-level setting of the `MergeTree` engine, for example
+    /// `setting("index_granularity", "4096")`. The value is used verbatim in the `SETTINGS`
+    /// clause of the `CREATE TABLE` statements, so string values must be quoted.
+    pub fn setting(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.schema.settings.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// run the `CREATE` and `DROP` statements of the non-temporary tables `ON CLUSTER`.
+    pub fn on_cluster(mut self, cluster_name: &str) -> Self {
+        self.schema.on_cluster = Some(cluster_name.to_string());
+        self
+    }
+
+    /// partition the tables by the given columns.
+    ///
+    /// The columns must already have been added to the builder - unknown columns
+    /// result in an error.
+    pub fn try_partition_by(mut self, columns: Vec<String>) -> Result<Self, Error> {
+        let unknown_columns: Vec<_> = columns
+            .iter()
+            .filter(|column_name| !self.schema.columns.contains_key(column_name.as_str()))
+            .cloned()
+            .collect();
+        if !unknown_columns.is_empty() {
+            return Err(Error::SchemaValidationError(
+                type_name::<CompactedTableSchema>(),
+                format!(
+                    "unknown columns to partition by: {}",
+                    unknown_columns.join(", ")
+                ),
+            ));
+        }
+        self.schema.partition_by_columns = columns;
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<CompactedTableSchema, Error> {
+        self.schema.validate()?;
+        Ok(self.schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clickhouse::compacted_tables::schema::{
+        identifiers_in_expression, validate_table_name, AggregationMethod, ClickhouseDataType,
+        ColumnDefinition, CompactedTableSchema, CompactedTableSchemaBuilder, CompressionMethod,
+        ResolutionMetadata, SimpleColumn, TableEngine, TemporalPartitioning, ValidateSchema,
+    };
+    use crate::clickhouse::compacted_tables::temporary_key::TemporaryKey;
+    use crate::clickhouse::compacted_tables::{Table, COL_NAME_H3INDEX};
+    use crate::Error;
+    use geo_types::Coord;
+    use h3ron::{H3Cell, Index};
+    use h3ron_polars::frame::H3DataFrame;
+    use h3ron_polars::FromIndexIterator;
+    use polars::prelude::{DataFrame, DataType, NamedFrom, Series};
+
+    #[test]
+    fn test_infer_from_dataframe() {
+        let cell = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 6).unwrap();
+        let cells: Vec<_> = cell.grid_disk(1).unwrap().iter().collect();
+        let num_cells = cells.len();
+        let mut h3index_series = Series::from_index_iter(cells.iter().copied());
+        h3index_series.rename(COL_NAME_H3INDEX);
+        let mut elephant_count: Vec<_> = (0..num_cells).map(|i| Some(i as u32)).collect();
+        elephant_count[0] = None;
+        let df = DataFrame::new(vec![
+            h3index_series,
+            Series::new("elephant_count", elephant_count),
+            Series::new("name", (0..num_cells).map(|_| "ellie").collect::<Vec<_>>()),
+        ])
+        .unwrap();
+        let h3df = H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX).unwrap();
+
+        let schema = CompactedTableSchemaBuilder::infer_from_dataframe("okavango_delta", &h3df)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(schema.h3_base_resolutions, vec![6]);
+        assert_eq!(schema.columns.len(), 3);
+        assert_eq!(
+            schema.columns.get("elephant_count"),
+            Some(&ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::UInt32,
+                None,
+                None,
+                true
+            )))
+        );
+        assert_eq!(
+            schema.columns.get("name").map(|def| def.datatype()),
+            Some(ClickhouseDataType::String)
+        );
+
+        // mixed resolutions
+        let mixed_df = DataFrame::new(vec![Series::new(
+            COL_NAME_H3INDEX,
+            &[cell.h3index(), cell.get_parent(5).unwrap().h3index()],
+        )])
+        .unwrap();
+        let mixed_h3df = H3DataFrame::from_dataframe(mixed_df, COL_NAME_H3INDEX).unwrap();
+        assert!(matches!(
+            CompactedTableSchemaBuilder::infer_from_dataframe("okavango_delta", &mixed_h3df),
+            Err(Error::MixedH3Resolutions)
+        ));
+    }
+
+    #[test]
+    fn test_comments() {
+        let schema = CompactedTableSchemaBuilder::new("okavango_delta")
+            .h3_base_resolutions(vec![1, 2, 3])
+            .description("elephants of the okavango delta")
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(
+                    SimpleColumn::new(ClickhouseDataType::UInt32, None, None, false)
+                        .with_comment("number of 'elephants'"),
+                ),
+            )
+            .build()
+            .unwrap();
+
+        for stmt in schema.build_create_statements(&None).unwrap() {
+            assert!(
+                stmt.contains(" elephant_count UInt32 COMMENT 'number of \\'elephants\\'' CODEC(")
+            );
+            assert!(stmt.ends_with(" COMMENT 'elephants of the okavango delta';"));
+        }
+    }
+
+    #[test]
+    fn test_add_column_ordered() {
+        let col =
+            |datatype| ColumnDefinition::Simple(SimpleColumn::new(datatype, None, None, false));
+        let schema = CompactedTableSchemaBuilder::new("okavango_delta")
+            .h3_base_resolutions(vec![1, 2, 3])
+            .add_column("elephant_count", col(ClickhouseDataType::UInt32))
+            .add_column_ordered("herd_id", col(ClickhouseDataType::UInt32), 2)
+            .add_column_desc("observed_on", col(ClickhouseDataType::DateTime), 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            schema.order_by_column_names(),
+            vec!["h3index", "observed_on", "herd_id"]
+        );
+        let stmt = schema.build_create_statements(&None).unwrap().remove(0);
+        assert!(stmt.contains("ORDER BY (h3index, observed_on DESC, herd_id)"));
+    }
+
+    #[test]
+    fn test_try_partition_by() {
+        let builder = CompactedTableSchemaBuilder::new("okavango_delta")
+            .h3_base_resolutions(vec![1, 2, 3])
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::DateTime,
+                    None,
+                    None,
+                    false,
+                )),
+            );
+        let err = builder
+            .clone()
+            .try_partition_by(vec!["observed_on".to_string(), "elephants".to_string()])
+            .unwrap_err();
+        assert!(err.to_string().contains("elephants"));
+        assert!(!err.to_string().contains("observed_on"));
+
+        let schema = builder
+            .try_partition_by(vec!["observed_on".to_string()])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(schema.partition_by_expressions().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_validate_table_name() {
+        assert!(validate_table_name("unittest", "").is_err());
+        assert!(validate_table_name("unittest", " test").is_err());
+        assert!(validate_table_name("unittest", "4test").is_err());
+        assert!(validate_table_name("unittest", "something").is_ok());
+        assert!(validate_table_name("unittest", "some_thing").is_ok());
+        assert!(validate_table_name("unittest", "x").is_ok());
+        assert!(validate_table_name("unittest", "a$b").is_err());
+    }
+
+    fn data_okavango_delta() -> CompactedTableSchema {
+        CompactedTableSchemaBuilder::new("okavango_delta")
+            .h3_resolution_range(1, 5)
+            .temporal_partitioning(TemporalPartitioning::Months(1))
+            .add_column(
+                "elephant_density",
                 ColumnDefinition::WithAggregation(
                     SimpleColumn::new(
                         ClickhouseDataType::Float32,
@@ -537,13 +1490,690 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "serde")]
+    fn test_column_codec_override() {
+        let statements = data_okavango_delta()
+            .build_create_statements(&None)
+            .unwrap();
+        for statement in statements.iter() {
+            // the codec of the column takes precedence over the codec of the schema
+            assert!(statement.contains("elephant_density Float32 CODEC(Delta(1))"));
+            assert!(statement.contains("observed_on DateTime CODEC(ZSTD(6))"));
+            assert!(statement.contains("h3index UInt64 CODEC(ZSTD(6))"));
+        }
+
+        let schema = CompactedTableSchemaBuilder::new("okavango_delta")
+            .h3_base_resolutions(vec![5])
+            .compression_method(CompressionMethod::LZ4HC(9))
+            .add_column(
+                "elephant_density",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::Float32,
+                    None,
+                    Some(CompressionMethod::Gorilla),
+                    false,
+                )),
+            )
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .build()
+            .unwrap();
+        for statement in schema.build_create_statements(&None).unwrap().iter() {
+            assert!(statement.contains("elephant_density Float32 CODEC(Gorilla)"));
+            assert!(statement.contains("elephant_count UInt32 CODEC(LZ4HC(9))"));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
     fn schema_json_roundtrip() {
         let s = data_okavango_delta();
         let json_string = serde_json::to_string(&s).unwrap();
         //println!("{}", json_string);
         let s2: CompactedTableSchema = serde_json::from_str(&json_string).unwrap();
         assert_eq!(s, s2);
+
+        // schemas serialized when the maximum resolution was stored as a field
+        let mut json_value = serde_json::to_value(&s).unwrap();
+        json_value
+            .as_object_mut()
+            .unwrap()
+            .insert("max_h3_resolution".to_string(), 5.into());
+        let s3: CompactedTableSchema = serde_json::from_value(json_value).unwrap();
+        assert_eq!(s, s3);
+    }
+
+    fn elephants_schema(extra_columns: &[(&str, ClickhouseDataType)]) -> CompactedTableSchema {
+        let mut builder = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![4, 5])
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            );
+        for (column_name, datatype) in extra_columns {
+            builder = builder.add_column(
+                column_name,
+                ColumnDefinition::Simple(SimpleColumn::new(*datatype, None, None, false)),
+            );
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_alter_add_column_statements() {
+        let existing = elephants_schema(&[]);
+        let schema = elephants_schema(&[("weight", ClickhouseDataType::Float32)]);
+
+        let statements = schema.alter_add_column_statements(&existing).unwrap();
+        assert_eq!(
+            statements.len(),
+            schema.get_resolution_metadata().unwrap().len()
+        );
+        assert!(statements.contains(
+            &"ALTER TABLE elephants_05_base ADD COLUMN IF NOT EXISTS weight Float32 CODEC(ZSTD(6));"
+                .to_string()
+        ));
+        assert!(statements
+            .iter()
+            .any(|stmt| stmt.starts_with("ALTER TABLE elephants_00_compacted ")));
+        assert!(statements
+            .iter()
+            .all(|stmt| !stmt.contains("elephant_count")));
+
+        // columns only present in the existing schema are ignored
+        assert!(existing
+            .alter_add_column_statements(&schema)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_diff_columns() {
+        let existing = elephants_schema(&[
+            ("weight", ClickhouseDataType::Float32),
+            ("name", ClickhouseDataType::String),
+        ]);
+        let schema = elephants_schema(&[
+            ("weight", ClickhouseDataType::Float64),
+            ("height", ClickhouseDataType::Float32),
+            ("age", ClickhouseDataType::UInt8),
+        ]);
+
+        let diff = schema.diff(&existing);
+        assert_eq!(diff.added_columns, vec!["age", "height"]);
+        assert_eq!(diff.removed_columns, vec!["name"]);
+        assert_eq!(diff.changed_columns.len(), 1);
+        assert_eq!(diff.changed_columns[0].0, "weight");
+        assert_eq!(
+            diff.changed_columns[0].1,
+            ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::Float32,
+                None,
+                None,
+                false
+            ))
+        );
+        assert!(!diff.engine_changed);
+        assert!(diff.resolutions_added.is_empty());
+        assert!(diff.resolutions_removed.is_empty());
+        assert!(!diff.is_empty());
+
+        assert!(schema.diff(&schema).is_empty());
+    }
+
+    #[test]
+    fn test_diff_added_columns_match_alter_statements() {
+        let existing = elephants_schema(&[("weight", ClickhouseDataType::Float32)]);
+        let schema = elephants_schema(&[
+            ("weight", ClickhouseDataType::Float32),
+            ("height", ClickhouseDataType::Float32),
+            ("age", ClickhouseDataType::UInt8),
+        ]);
+
+        let diff = schema.diff(&existing);
+        let statements = schema.alter_add_column_statements(&existing).unwrap();
+        for statement in statements {
+            let added_columns: Vec<_> = statement
+                .split("ADD COLUMN IF NOT EXISTS ")
+                .skip(1)
+                .map(|column_sql| column_sql.split(' ').next().unwrap())
+                .collect();
+            assert_eq!(added_columns, diff.added_columns);
+        }
+    }
+
+    #[test]
+    fn test_diff_engine_and_resolutions() {
+        let existing = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![3, 4, 5])
+            .build()
+            .unwrap();
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5, 6, 4])
+            .table_engine(TableEngine::ReplacingMergeTree(None))
+            .build()
+            .unwrap();
+
+        let diff = schema.diff(&existing);
+        assert!(diff.engine_changed);
+        assert_eq!(diff.resolutions_added, vec![6]);
+        assert_eq!(diff.resolutions_removed, vec![3]);
+        assert!(diff.added_columns.is_empty());
+        assert!(diff.removed_columns.is_empty());
+        assert!(diff.changed_columns.is_empty());
+    }
+
+    #[test]
+    fn test_alter_add_column_statements_no_change() {
+        let schema = elephants_schema(&[("weight", ClickhouseDataType::Float32)]);
+        assert!(schema
+            .alter_add_column_statements(&schema)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_alter_add_column_statements_type_conflict() {
+        let existing = elephants_schema(&[("weight", ClickhouseDataType::Float32)]);
+        let schema = elephants_schema(&[("weight", ClickhouseDataType::Float64)]);
+        assert!(matches!(
+            schema.alter_add_column_statements(&existing),
+            Err(Error::IncompatibleDatatype(column_name, _, _)) if column_name == "weight"
+        ));
+    }
+
+    #[test]
+    fn test_h3_resolution_range() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![9])
+            .h3_resolution_range(2, 5)
+            .build()
+            .unwrap();
+        assert_eq!(schema.h3_base_resolutions, vec![2, 3, 4, 5]);
+        assert_eq!(schema.max_h3_resolution().unwrap(), 5);
+
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_resolution_range(7, 7)
+            .build()
+            .unwrap();
+        assert_eq!(schema.h3_base_resolutions, vec![7]);
+
+        assert!(matches!(
+            CompactedTableSchemaBuilder::new("elephants")
+                .h3_resolution_range(0, 16)
+                .build(),
+            Err(Error::UnsupportedH3Resolution(16))
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_h3_resolution_range_inverted() {
+        let _ = CompactedTableSchemaBuilder::new("elephants").h3_resolution_range(5, 2);
+    }
+
+    #[test]
+    fn test_identifiers_in_expression() {
+        assert_eq!(identifiers_in_expression("h3index"), vec!["h3index"]);
+        assert_eq!(
+            identifiers_in_expression("intHash32 (h3index) + toYear(observed_on) + h3index"),
+            vec!["h3index", "observed_on"]
+        );
+        assert!(identifiers_in_expression("42").is_empty());
+    }
+
+    #[test]
+    fn test_sample_by() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .sample_by(COL_NAME_H3INDEX)
+            .build()
+            .unwrap();
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .all(|stmt| stmt.contains("ORDER BY (h3index) SAMPLE BY (h3index);")));
+
+        assert!(CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .sample_by("intHash32(elephant_count)")
+            .build()
+            .is_err());
+    }
+
+    fn aggregated_column(agg_method: AggregationMethod) -> ColumnDefinition {
+        ColumnDefinition::WithAggregation(
+            SimpleColumn::new(ClickhouseDataType::UInt32, None, None, false),
+            agg_method,
+        )
+    }
+
+    #[test]
+    fn test_create_materialized_view_statements() {
+        let aggregate_function = |function: &str| ColumnDefinition::AggregateFunction {
+            function: function.to_string(),
+            inner_type: ClickhouseDataType::Float32,
+        };
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5, 3, 4])
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .add_column("elephant_count", aggregated_column(AggregationMethod::Sum))
+            .add_column("max_herd_size", aggregate_function("max"))
+            .add_column("max_weight", aggregated_column(AggregationMethod::Max))
+            .add_column("avg_weight", aggregate_function("avg"))
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::Date,
+                    Some(0),
+                    None,
+                    false,
+                )),
+            )
+            .build()
+            .unwrap();
+
+        let statements = schema.create_materialized_view_statements().unwrap();
+        assert_eq!(statements, vec![
+            "CREATE MATERIALIZED VIEW IF NOT EXISTS elephants_04_base_to_03_mv TO elephants_03_base AS SELECT h3ToParent(src.h3index, 3) AS h3index, avgMergeState(src.avg_weight) AS avg_weight, sum(src.elephant_count) AS elephant_count, maxMergeState(src.max_herd_size) AS max_herd_size, max(src.max_weight) AS max_weight, src.observed_on AS observed_on FROM elephants_04_base AS src GROUP BY h3ToParent(src.h3index, 3), src.observed_on;".to_string(),
+            "CREATE MATERIALIZED VIEW IF NOT EXISTS elephants_05_base_to_04_mv TO elephants_04_base AS SELECT h3ToParent(src.h3index, 4) AS h3index, avgMergeState(src.avg_weight) AS avg_weight, sum(src.elephant_count) AS elephant_count, maxMergeState(src.max_herd_size) AS max_herd_size, max(src.max_weight) AS max_weight, src.observed_on AS observed_on FROM elephants_05_base AS src GROUP BY h3ToParent(src.h3index, 4), src.observed_on;".to_string(),
+        ]);
+
+        // the views must not be mistaken for tables of the tableset
+        assert!(Table::parse("elephants_05_base_to_04_mv").is_none());
+    }
+
+    #[test]
+    fn test_create_materialized_view_statements_single_resolution() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![5])
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .on_cluster("savanna")
+            .add_column("elephant_count", aggregated_column(AggregationMethod::Sum))
+            .build()
+            .unwrap();
+        assert!(schema
+            .create_materialized_view_statements()
+            .unwrap()
+            .is_empty());
+
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![3, 5])
+            .table_engine(TableEngine::SummingMergeTree(vec![
+                "elephant_count".to_string()
+            ]))
+            .on_cluster("savanna")
+            .add_column(
+                "elephant_count",
+                aggregated_column(AggregationMethod::RelativeToCellArea),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            schema.create_materialized_view_statements().unwrap(),
+            vec!["CREATE MATERIALIZED VIEW IF NOT EXISTS elephants_05_base_to_03_mv ON CLUSTER savanna TO elephants_03_base AS SELECT h3ToParent(src.h3index, 3) AS h3index, (sum(src.elephant_count) / length(h3ToChildren(h3ToParent(src.h3index, 3), 5))) AS elephant_count FROM elephants_05_base AS src GROUP BY h3ToParent(src.h3index, 3);".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_create_materialized_view_statements_unmergeable() {
+        let builder = CompactedTableSchemaBuilder::new("elephants").h3_base_resolutions(vec![4, 5]);
+
+        // the partial rows would replace each other
+        let schema = builder
+            .clone()
+            .add_column("elephant_count", aggregated_column(AggregationMethod::Sum))
+            .build()
+            .unwrap();
+        assert!(schema.create_materialized_view_statements().is_err());
+
+        // the values would not get summed when merging the partial rows
+        let schema = builder
+            .clone()
+            .table_engine(TableEngine::SummingMergeTree(vec!["herd_size".to_string()]))
+            .add_column("elephant_count", aggregated_column(AggregationMethod::Sum))
+            .add_column("herd_size", aggregated_column(AggregationMethod::Sum))
+            .build()
+            .unwrap();
+        assert!(schema.create_materialized_view_statements().is_err());
+
+        // the average can not be computed from partial averages
+        let schema = builder
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .add_column("herd_size", aggregated_column(AggregationMethod::Average))
+            .build()
+            .unwrap();
+        assert!(schema.create_materialized_view_statements().is_err());
+    }
+
+    #[test]
+    fn test_max_min_columns_merged_by_engine() {
+        let builder = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![4, 5])
+            .add_column("max_weight", aggregated_column(AggregationMethod::Max))
+            .add_column("min_weight", aggregated_column(AggregationMethod::Min));
+
+        // replacing merge trees do not merge values
+        let statement = builder
+            .clone()
+            .build()
+            .unwrap()
+            .build_create_statements(&None)
+            .unwrap()[0]
+            .clone();
+        assert!(statement.contains(" max_weight UInt32 CODEC("));
+
+        let schema = builder
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .build()
+            .unwrap();
+        for statement in schema.build_create_statements(&None).unwrap() {
+            assert!(statement.contains(" max_weight SimpleAggregateFunction(max, UInt32) CODEC("));
+            assert!(statement.contains(" min_weight SimpleAggregateFunction(min, UInt32) CODEC("));
+        }
+        assert_eq!(
+            schema.create_materialized_view_statements().unwrap(),
+            vec!["CREATE MATERIALIZED VIEW IF NOT EXISTS elephants_05_base_to_04_mv TO elephants_04_base AS \
+            SELECT h3ToParent(src.h3index, 4) AS h3index, max(src.max_weight) AS max_weight, min(src.min_weight) AS min_weight \
+            FROM elephants_05_base AS src GROUP BY h3ToParent(src.h3index, 4);"
+                .to_string()]
+        );
+        assert_eq!(
+            schema.create_view_statement("elephants_all").unwrap(),
+            "CREATE VIEW IF NOT EXISTS elephants_all AS \
+            select h3index, max(max_weight) as max_weight, min(min_weight) as min_weight from elephants_04_base group by h3index \
+            union all \
+            select h3index, max(max_weight) as max_weight, min(min_weight) as min_weight from elephants_05_base group by h3index"
+        );
+    }
+
+    #[test]
+    fn test_add_columns_from_polars_schema() {
+        let df = DataFrame::new(vec![
+            Series::new(COL_NAME_H3INDEX, &[0x89283080ddbffff_u64]),
+            Series::new("elephant_count", &[3_u32]),
+            Series::new("name", &["Jumbo"]),
+            Series::new("weight", &[4.5_f64]),
+        ])
+        .unwrap();
+
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![9])
+            .add_columns_from_polars_schema(&df.schema(), &["weight"])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(schema.columns.len(), 3);
+        assert_eq!(
+            schema.columns.get("elephant_count"),
+            Some(&ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::UInt32,
+                None,
+                None,
+                false
+            )))
+        );
+        assert_eq!(
+            schema.columns.get("name"),
+            Some(&ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::String,
+                None,
+                None,
+                false
+            )))
+        );
+        assert!(!schema.columns.contains_key("weight"));
+        assert_eq!(
+            schema.columns.get(COL_NAME_H3INDEX),
+            Some(&ColumnDefinition::H3Index)
+        );
+
+        let df = DataFrame::new(vec![Series::new("flag", &[true])]).unwrap();
+        assert!(matches!(
+            CompactedTableSchemaBuilder::new("elephants")
+                .add_columns_from_polars_schema(&df.schema(), &[]),
+            Err(Error::UnknownDatatype(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_base_suffix() {
+        let schema = CompactedTableSchemaBuilder::new("t")
+            .no_base_suffix()
+            .h3_base_resolutions(vec![5])
+            .build()
+            .unwrap();
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .any(|stmt| stmt.starts_with("CREATE TABLE IF NOT EXISTS t_05 (")));
+        assert!(statements.iter().all(|stmt| !stmt.contains("t_05_base")));
+        assert!(statements
+            .iter()
+            .any(|stmt| stmt.starts_with("CREATE TABLE IF NOT EXISTS t_05_compacted (")));
+    }
+
+    #[test]
+    fn test_settings() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .setting("merge_max_block_size", "8192")
+            .setting("index_granularity", "4096")
+            .build()
+            .unwrap();
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements.iter().all(|stmt| stmt.contains(
+            "ORDER BY (h3index) SETTINGS index_granularity=4096, merge_max_block_size=8192;"
+        )));
+
+        assert!(CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .setting("index_granularity=1; DROP TABLE x", "4096")
+            .build()
+            .is_err());
+        assert!(CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .setting("index_granularity", " ")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn sample_by_json_roundtrip() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .sample_by(COL_NAME_H3INDEX)
+            .build()
+            .unwrap();
+        let json_string = serde_json::to_string(&schema).unwrap();
+        let schema2: CompactedTableSchema = serde_json::from_str(&json_string).unwrap();
+        assert_eq!(schema, schema2);
+    }
+
+    #[test]
+    fn test_replacing_merge_tree_version_column() {
+        let builder = || {
+            CompactedTableSchemaBuilder::new("elephants")
+                .h3_base_resolutions(vec![1, 2])
+                .add_column(
+                    "version",
+                    ColumnDefinition::Simple(SimpleColumn::new(
+                        ClickhouseDataType::UInt32,
+                        None,
+                        None,
+                        false,
+                    )),
+                )
+                .add_column(
+                    "weight",
+                    ColumnDefinition::Simple(SimpleColumn::new(
+                        ClickhouseDataType::Float32,
+                        None,
+                        None,
+                        false,
+                    )),
+                )
+        };
+
+        let schema = builder()
+            .table_engine(TableEngine::ReplacingMergeTree(Some("version".to_string())))
+            .build()
+            .unwrap();
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .all(|stmt| stmt.contains("ENGINE ReplacingMergeTree(version) ")));
+
+        let schema = builder()
+            .table_engine(TableEngine::ReplacingMergeTree(Some("version".to_string())))
+            .with_replication("/clickhouse/tables/{shard}", "{replica}")
+            .build()
+            .unwrap();
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements.iter().any(|stmt| stmt.contains(
+            "ENGINE ReplicatedReplacingMergeTree('/clickhouse/tables/{shard}/elephants_01_base', '{replica}', version) "
+        )));
+
+        for version_column in ["weight", "unknown"] {
+            assert!(builder()
+                .table_engine(TableEngine::ReplacingMergeTree(Some(
+                    version_column.to_string()
+                )))
+                .build()
+                .is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn table_engine_json_compatibility() {
+        // schemas serialized before the version column existed
+        let table_engine: TableEngine = serde_json::from_str(r#""ReplacingMergeTree""#).unwrap();
+        assert_eq!(table_engine, TableEngine::ReplacingMergeTree(None));
+
+        for table_engine in [
+            TableEngine::ReplacingMergeTree(None),
+            TableEngine::ReplacingMergeTree(Some("version".to_string())),
+            TableEngine::SummingMergeTree(vec!["weight".to_string()]),
+            TableEngine::AggregatingMergeTree,
+        ] {
+            let json_string = serde_json::to_string(&table_engine).unwrap();
+            let table_engine2: TableEngine = serde_json::from_str(&json_string).unwrap();
+            assert_eq!(table_engine, table_engine2);
+        }
+    }
+
+    #[test]
+    fn test_ttl() {
+        let builder = || {
+            CompactedTableSchemaBuilder::new("elephants")
+                .h3_base_resolutions(vec![1, 2])
+                .add_column(
+                    "observed_on",
+                    ColumnDefinition::Simple(SimpleColumn::new(
+                        ClickhouseDataType::DateTime,
+                        None,
+                        None,
+                        false,
+                    )),
+                )
+                .add_column(
+                    "elephant_count",
+                    ColumnDefinition::Simple(SimpleColumn::new(
+                        ClickhouseDataType::UInt32,
+                        None,
+                        None,
+                        false,
+                    )),
+                )
+        };
+        let schema = builder().ttl("observed_on", 30).build().unwrap();
+
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .all(|stmt| stmt
+                .contains("ORDER BY (h3index) TTL observed_on + INTERVAL 30 DAY DELETE;")));
+
+        // temporary tables do not expire
+        let statements = schema
+            .build_create_statements(&Some(TemporaryKey::new()))
+            .unwrap();
+        assert!(statements.iter().all(|stmt| !stmt.contains(" TTL ")));
+
+        assert!(builder().ttl("elephant_count", 30).build().is_err());
+        assert!(builder().ttl("unknown", 30).build().is_err());
+        assert!(builder().ttl("observed_on", 0).build().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn ttl_json_roundtrip() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::Date,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .ttl("observed_on", 7)
+            .build()
+            .unwrap();
+        let json_string = serde_json::to_string(&schema).unwrap();
+        let schema2: CompactedTableSchema = serde_json::from_str(&json_string).unwrap();
+        assert_eq!(schema, schema2);
+    }
+
+    #[test]
+    fn test_temporal_partitioning_day() {
+        let schema = CompactedTableSchemaBuilder::new("satellite_passes")
+            .h3_base_resolutions(vec![1, 2])
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::DateTime,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .temporal_partitioning(TemporalPartitioning::Day)
+            .build()
+            .unwrap();
+        assert!(schema.validate().is_ok());
+
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .all(|stmt| stmt.contains("toString(toDate(observed_on))")));
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn temporal_partitioning_json_compatibility() {
+        // schemas serialized before the `Day` variant existed must still deserialize
+        let tp: TemporalPartitioning = serde_json::from_str(r#"{"Months":3}"#).unwrap();
+        assert_eq!(tp, TemporalPartitioning::Months(3));
+
+        let json_string = serde_json::to_string(&TemporalPartitioning::Day).unwrap();
+        let tp: TemporalPartitioning = serde_json::from_str(&json_string).unwrap();
+        assert_eq!(tp, TemporalPartitioning::Day);
     }
 
     #[test]
@@ -551,6 +2181,322 @@ mod tests {
         data_okavango_delta();
     }
 
+    #[test]
+    fn with_replication() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .with_replication("/clickhouse/tables/{shard}", "{replica}")
+            .build()
+            .unwrap();
+
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert!(statements
+            .iter()
+            .all(|stmt| stmt.contains("ENGINE ReplicatedReplacingMergeTree(")));
+        assert!(statements.iter().any(|stmt| stmt.contains(
+            "ENGINE ReplicatedReplacingMergeTree('/clickhouse/tables/{shard}/elephants_01_base', '{replica}')"
+        )));
+
+        // temporary tables are not replicated
+        let tmp_statements = schema
+            .build_create_statements(&Some(TemporaryKey::new()))
+            .unwrap();
+        assert!(tmp_statements
+            .iter()
+            .all(|stmt| stmt.contains("ENGINE ReplacingMergeTree ")));
+
+        let schema2 = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .with_replication("/clickhouse/tables/{shard}/{database}/{table}", "{replica}")
+            .build()
+            .unwrap();
+        assert!(schema2.build_create_statements(&None).unwrap()[0].contains(
+            "ENGINE ReplicatedSummingMergeTree('/clickhouse/tables/{shard}/{database}/{table}', '{replica}')"
+        ));
+
+        assert!(CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .with_replication("/clickhouse/tables/'", "{replica}")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn min_max_h3_resolution() {
+        let schema = data_okavango_delta();
+        assert_eq!(schema.min_h3_resolution().unwrap(), 1);
+        assert_eq!(schema.max_h3_resolution().unwrap(), 5);
+
+        let mut schema_without_resolutions = schema;
+        schema_without_resolutions.h3_base_resolutions.clear();
+        assert!(schema_without_resolutions.min_h3_resolution().is_err());
+        assert!(schema_without_resolutions.max_h3_resolution().is_err());
+    }
+
+    #[test]
+    fn create_view_statement() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![2, 1])
+            .table_engine(TableEngine::SummingMergeTree(vec![
+                "elephant_count".to_string()
+            ]))
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::Date,
+                    Some(0),
+                    None,
+                    false,
+                )),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            schema.create_view_statement("elephants_all").unwrap(),
+            "CREATE VIEW IF NOT EXISTS elephants_all AS \
+            select h3index, sum(elephant_count) as elephant_count, any(observed_on) as observed_on from elephants_01_base group by h3index \
+            union all \
+            select h3index, sum(elephant_count) as elephant_count, any(observed_on) as observed_on from elephants_02_base group by h3index"
+        );
+        assert!(schema.create_view_statement("4elephants").is_err());
+
+        // replacing merge trees have no aggregation semantics
+        assert!(data_okavango_delta()
+            .create_view_statement("okavango_delta_all")
+            .is_err());
+    }
+
+    #[test]
+    fn create_view_statement_sum_all_numeric_columns() {
+        // without an explicit list of columns the engine sums all numeric non-key columns
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1])
+            .table_engine(TableEngine::SummingMergeTree(vec![]))
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .add_column(
+                "herd_id",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt16,
+                    Some(0),
+                    None,
+                    false,
+                )),
+            )
+            .add_column(
+                "name",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::String,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            schema.create_view_statement("elephants_all").unwrap(),
+            "CREATE VIEW IF NOT EXISTS elephants_all AS \
+            select h3index, sum(elephant_count) as elephant_count, any(herd_id) as herd_id, any(name) as name from elephants_01_base group by h3index"
+        );
+    }
+
+    #[test]
+    fn aggregating_merge_tree() {
+        let builder = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![4, 5])
+            .use_compacted_resolutions(false)
+            .table_engine(TableEngine::AggregatingMergeTree)
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::Date,
+                    Some(0),
+                    None,
+                    false,
+                )),
+            );
+
+        // the engine requires at least one column storing aggregation states
+        assert!(builder.clone().build().is_err());
+        assert!(builder
+            .clone()
+            .add_column(
+                "avg_weight",
+                ColumnDefinition::AggregateFunction {
+                    function: "avg(".to_string(),
+                    inner_type: ClickhouseDataType::Float32,
+                },
+            )
+            .build()
+            .is_err());
+
+        let schema = builder
+            .add_column(
+                "avg_weight",
+                ColumnDefinition::AggregateFunction {
+                    function: "avg".to_string(),
+                    inner_type: ClickhouseDataType::Float32,
+                },
+            )
+            .build()
+            .unwrap();
+
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert_eq!(statements.len(), 2);
+        for statement in statements.iter() {
+            assert!(statement.contains(" ENGINE AggregatingMergeTree "));
+            assert!(statement.contains("avg_weight AggregateFunction(avg, Float32) CODEC(ZSTD(6))"));
+            assert!(statement.contains("observed_on Date CODEC(ZSTD(6))"));
+            assert!(statement.contains("ORDER BY (h3index, observed_on)"));
+        }
+
+        assert_eq!(
+            schema.create_materialized_view_statements().unwrap(),
+            vec!["CREATE MATERIALIZED VIEW IF NOT EXISTS elephants_05_base_to_04_mv TO elephants_04_base AS \
+            SELECT h3ToParent(src.h3index, 4) AS h3index, avgMergeState(src.avg_weight) AS avg_weight, src.observed_on AS observed_on \
+            FROM elephants_05_base AS src GROUP BY h3ToParent(src.h3index, 4), src.observed_on;"
+                .to_string()]
+        );
+
+        assert_eq!(
+            schema.create_view_statement("elephants_all").unwrap(),
+            "CREATE VIEW IF NOT EXISTS elephants_all AS \
+            select h3index, avgMerge(avg_weight) as avg_weight, any(observed_on) as observed_on from elephants_04_base group by h3index \
+            union all \
+            select h3index, avgMerge(avg_weight) as avg_weight, any(observed_on) as observed_on from elephants_05_base group by h3index"
+        );
+    }
+
+    #[test]
+    fn without_compacted_resolutions() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .use_compacted_resolutions(false)
+            .build()
+            .unwrap();
+        assert!(!schema.use_compaction);
+        assert!(schema
+            .get_resolution_metadata()
+            .unwrap()
+            .iter()
+            .all(|rm| !rm.is_compacted));
+        assert!(schema
+            .build_create_statements(&None)
+            .unwrap()
+            .iter()
+            .all(|stmt| !stmt.contains("_compacted")));
+    }
+
+    #[test]
+    fn on_cluster() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .on_cluster("savanna")
+            .build()
+            .unwrap();
+
+        let create_statements = schema.build_create_statements(&None).unwrap();
+        assert!(create_statements.iter().any(|stmt| stmt
+            .starts_with("CREATE TABLE IF NOT EXISTS elephants_01_base ON CLUSTER savanna (")));
+        let drop_statements = schema.build_drop_statements(&None).unwrap();
+        assert!(drop_statements
+            .iter()
+            .any(|stmt| stmt == "drop table if exists elephants_01_base ON CLUSTER savanna"));
+
+        // temporary tables are only created locally
+        let temporary_key = Some(TemporaryKey::new());
+        assert!(schema
+            .build_create_statements(&temporary_key)
+            .unwrap()
+            .iter()
+            .chain(schema.build_drop_statements(&temporary_key).unwrap().iter())
+            .all(|stmt| !stmt.contains("ON CLUSTER")));
+
+        for invalid_name in ["", "1savanna", "savanna; drop table elephants_01_base"] {
+            assert!(CompactedTableSchemaBuilder::new("elephants")
+                .h3_base_resolutions(vec![1, 2])
+                .on_cluster(invalid_name)
+                .build()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn to_insert_sample() {
+        let h3df = data_okavango_delta().to_insert_sample().unwrap();
+        assert_eq!(h3df.dataframe().height(), 0);
+        assert_eq!(
+            h3df.dataframe().get_column_names(),
+            vec!["h3index", "elephant_density", "observed_on"]
+        );
+        assert_eq!(
+            h3df.dataframe().column("elephant_density").unwrap().dtype(),
+            &DataType::Float32
+        );
+    }
+
+    #[test]
+    fn summing_merge_tree_requires_numeric_columns() {
+        let builder = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2, 3])
+            .add_column(
+                "elephant_count",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::UInt32,
+                    None,
+                    None,
+                    false,
+                )),
+            )
+            .add_column(
+                "observed_on",
+                ColumnDefinition::Simple(SimpleColumn::new(
+                    ClickhouseDataType::DateTime,
+                    Some(0),
+                    None,
+                    false,
+                )),
+            );
+
+        assert!(builder
+            .clone()
+            .table_engine(TableEngine::SummingMergeTree(vec![
+                "elephant_count".to_string()
+            ]))
+            .build()
+            .is_ok());
+
+        let err = builder
+            .table_engine(TableEngine::SummingMergeTree(vec![
+                "elephant_count".to_string(),
+                "observed_on".to_string(),
+            ]))
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("observed_on"));
+        assert!(!err.to_string().contains("elephant_count"));
+    }
+
     #[test]
     fn resolution_metadata_sort() {
         let mut v1 = vec![
@@ -7,9 +7,11 @@ use crate::clickhouse::compacted_tables::schema::ValidateSchema;
 use crate::Error;
 
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "use_serde", serde(from = "TableEngineRepr"))]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TableEngine {
-    ReplacingMergeTree,
+    /// optionally using a version column to decide which of the duplicated rows is kept.
+    ReplacingMergeTree(Option<String>),
     SummingMergeTree(Vec<String>),
     AggregatingMergeTree,
 }
@@ -17,7 +19,140 @@ pub enum TableEngine {
 #[allow(clippy::derivable_impls)]
 impl Default for TableEngine {
     fn default() -> Self {
-        TableEngine::ReplacingMergeTree
+        TableEngine::ReplacingMergeTree(None)
+    }
+}
+
+/// deserialization of the table engine accepting the schemas serialized before
+/// `ReplacingMergeTree` got its version column.
+#[cfg(feature = "use_serde")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TableEngineRepr {
+    Current(CurrentTableEngine),
+    Legacy(LegacyTableEngine),
+}
+
+#[cfg(feature = "use_serde")]
+#[derive(Deserialize)]
+enum CurrentTableEngine {
+    ReplacingMergeTree(Option<String>),
+    SummingMergeTree(Vec<String>),
+    AggregatingMergeTree,
+}
+
+#[cfg(feature = "use_serde")]
+#[derive(Deserialize)]
+enum LegacyTableEngine {
+    ReplacingMergeTree,
+}
+
+#[cfg(feature = "use_serde")]
+impl From<TableEngineRepr> for TableEngine {
+    fn from(repr: TableEngineRepr) -> Self {
+        match repr {
+            TableEngineRepr::Current(CurrentTableEngine::ReplacingMergeTree(version_column)) => {
+                Self::ReplacingMergeTree(version_column)
+            }
+            TableEngineRepr::Current(CurrentTableEngine::SummingMergeTree(sum_columns)) => {
+                Self::SummingMergeTree(sum_columns)
+            }
+            TableEngineRepr::Current(CurrentTableEngine::AggregatingMergeTree) => {
+                Self::AggregatingMergeTree
+            }
+            TableEngineRepr::Legacy(LegacyTableEngine::ReplacingMergeTree) => {
+                Self::ReplacingMergeTree(None)
+            }
+        }
+    }
+}
+
+impl TableEngine {
+    pub(crate) fn sql_expression(
+        &self,
+        replication: Option<&ReplicationConfig>,
+        table_name: &str,
+    ) -> String {
+        let (engine_name, mut params) = match self {
+            Self::ReplacingMergeTree(version_column) => (
+                "ReplacingMergeTree",
+                version_column.iter().cloned().collect(),
+            ),
+            Self::SummingMergeTree(smt_columns) => ("SummingMergeTree", smt_columns.clone()),
+            Self::AggregatingMergeTree => ("AggregatingMergeTree", vec![]),
+        };
+        let engine_name = if let Some(replication) = replication {
+            params.insert(0, replication.engine_parameters(table_name));
+            format!("Replicated{}", engine_name)
+        } else {
+            engine_name.to_string()
+        };
+        // SummingMergeTree always had its parameter list rendered, even when empty
+        if params.is_empty() && !matches!(self, Self::SummingMergeTree(_)) {
+            engine_name
+        } else {
+            format!("{}({})", engine_name, params.join(", "))
+        }
+    }
+}
+
+/// Replication of the tables using the `Replicated*MergeTree` table engines.
+///
+/// Both values are passed to ClickHouse unchanged, so the macros configured on the server
+/// like `{shard}` and `{replica}` can be used.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ReplicationConfig {
+    /// path of the tables in ZooKeeper. The path must be unique for each table, so the
+    /// name of the table gets appended when the path does not contain the `{table}` macro.
+    pub zookeeper_path: String,
+
+    /// name of the replica. Typically `{replica}`.
+    pub replica_name: String,
+}
+
+impl ReplicationConfig {
+    pub fn new(zookeeper_path: &str, replica_name: &str) -> Self {
+        Self {
+            zookeeper_path: zookeeper_path.to_string(),
+            replica_name: replica_name.to_string(),
+        }
+    }
+
+    fn engine_parameters(&self, table_name: &str) -> String {
+        let zookeeper_path = if self.zookeeper_path.contains("{table}") {
+            self.zookeeper_path.clone()
+        } else {
+            format!(
+                "{}/{}",
+                self.zookeeper_path.trim_end_matches('/'),
+                table_name
+            )
+        };
+        format!("'{}', '{}'", zookeeper_path, self.replica_name)
+    }
+}
+
+impl ValidateSchema for ReplicationConfig {
+    fn validate(&self) -> Result<(), Error> {
+        for (name, value) in [
+            ("zookeeper_path", &self.zookeeper_path),
+            ("replica_name", &self.replica_name),
+        ] {
+            if value.is_empty() {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!("{} must not be empty", name),
+                ));
+            }
+            if value.contains('\'') {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!("{} must not contain quotes", name),
+                ));
+            }
+        }
+        Ok(())
     }
 }
 
@@ -64,6 +199,45 @@ impl ValidateSchema for CompressionMethod {
     }
 }
 
+/// Expiration of rows using the `TTL` of the ClickHouse tables.
+///
+/// Rows get deleted once the value of the temporal column `column_name` is older than
+/// `interval_days` days.
+#[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TtlConfig {
+    pub column_name: String,
+    pub interval_days: u32,
+}
+
+impl TtlConfig {
+    pub fn new(column_name: &str, interval_days: u32) -> Self {
+        Self {
+            column_name: column_name.to_string(),
+            interval_days,
+        }
+    }
+
+    pub(crate) fn sql_expression(&self) -> String {
+        format!(
+            "{} + INTERVAL {} DAY DELETE",
+            self.column_name, self.interval_days
+        )
+    }
+}
+
+impl ValidateSchema for TtlConfig {
+    fn validate(&self) -> Result<(), Error> {
+        if self.interval_days == 0 {
+            return Err(Error::SchemaValidationError(
+                type_name::<Self>(),
+                "interval_days must be > 0".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
 fn compression_level_out_of_range(location: &'static str) -> Error {
     Error::SchemaValidationError(location, "compression level out of range".to_string())
 }
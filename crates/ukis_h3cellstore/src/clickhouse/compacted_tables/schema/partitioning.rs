@@ -23,6 +23,9 @@ impl Default for TemporalResolution {
 #[derive(Debug, PartialEq, Eq, Clone)]
 #[cfg_attr(feature = "use_serde", derive(Serialize, Deserialize))]
 pub enum TemporalPartitioning {
+    /// Daily partitions for datasets with multiple observations per day
+    Day,
+
     /// Monthly/multi-month partitions
     Months(u8),
 
@@ -39,6 +42,7 @@ impl Default for TemporalPartitioning {
 impl ValidateSchema for TemporalPartitioning {
     fn validate(&self) -> Result<(), Error> {
         match self {
+            Self::Day => {}
             Self::Months(num_months) => {
                 if *num_months == 0 {
                     return Err(Error::SchemaValidationError(
@@ -66,6 +70,7 @@ impl TemporalPartitioning {
         S: AsRef<str>,
     {
         match self {
+            Self::Day => format!("toString(toDate({}))", column_name.as_ref()),
             Self::Months(num_months) => {
                 format!(
                     "toString(ceil(((toYear({}) * 100) + (100 * (toMonth({}) - 1) / 12)) / {}))",
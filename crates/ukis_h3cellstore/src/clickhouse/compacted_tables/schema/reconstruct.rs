@@ -0,0 +1,396 @@
+//! Reconstruction of [`CompactedTableSchema`]s from the tables existing in ClickHouse.
+//!
+//! Not everything of a schema is stored in ClickHouse. The aggregation methods of the columns
+//! as well as the replication and the partitioning settings can not be recovered, so all columns
+//! besides the `SimpleAggregateFunction` columns of the `Max` and `Min` aggregations are
+//! reconstructed as [`ColumnDefinition::Simple`] and the defaults are used for the rest.
+
+use std::any::type_name;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tracing::warn;
+use ukis_clickhouse_arrow_grpc::{ArrowInterface, QueryInfo};
+
+use crate::clickhouse::compacted_tables::schema::{
+    AggregationMethod, ClickhouseDataType, ColumnDefinition, CompactedTableSchema,
+    CompactedTableSchemaBuilder, SimpleColumn, TableEngine,
+};
+use crate::clickhouse::compacted_tables::{CompactedTablesStore, COL_NAME_H3INDEX};
+use crate::Error;
+
+impl CompactedTableSchema {
+    /// reconstruct the schema of an existing tableset from the metadata stored in ClickHouse.
+    ///
+    /// Columns using datatypes not supported by the schemas are skipped with a warning.
+    pub async fn from_clickhouse<C, S1, S2>(
+        client: &mut C,
+        database_name: S1,
+        tableset_name: S2,
+    ) -> Result<Self, Error>
+    where
+        C: ArrowInterface + Send + Clone + Sync,
+        S1: AsRef<str> + Send + Sync,
+        S2: AsRef<str> + Send + Sync,
+    {
+        let tableset = client
+            .get_tableset(database_name.as_ref(), tableset_name.as_ref())
+            .await?;
+
+        let base_tables = tableset.base_tables();
+        let reference_table = base_tables
+            .iter()
+            .max_by_key(|table| table.spec.h3_resolution)
+            .ok_or_else(|| Error::TableSetNotFound(tableset_name.as_ref().to_string()))?;
+
+        let tables_df = client
+            .execute_into_dataframe(QueryInfo {
+                query: format!(
+                    "select engine_full, sorting_key, partition_key from system.tables where database = currentDatabase() and name = '{}'",
+                    reference_table.to_table_name()
+                ),
+                database: database_name.as_ref().to_string(),
+                ..Default::default()
+            })
+            .await?;
+        let table_metadata = |column_name: &str| -> Result<String, Error> {
+            Ok(tables_df
+                .column(column_name)?
+                .utf8()?
+                .get(0)
+                .unwrap_or_default()
+                .to_string())
+        };
+        let engine_full = table_metadata("engine_full")?;
+        let sorting_key = split_key_expression(&table_metadata("sorting_key")?);
+        let partition_key = table_metadata("partition_key")?;
+
+        let mut builder = CompactedTableSchemaBuilder::new(&tableset.basename)
+            .table_engine(parse_table_engine(&engine_full)?)
+            .h3_base_resolutions(tableset.base_resolutions())
+            .use_compacted_resolutions(!tableset.compacted_tables.is_empty());
+        builder.schema.has_base_suffix = reference_table.spec.has_base_suffix;
+
+        let mut partition_by = vec![];
+        for (column_name, column_type) in tableset.columns.iter() {
+            let (aggregation, column_type) = parse_simple_aggregation(column_type);
+            let (datatype, nullable) = match parse_column_type(column_type) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    warn!(
+                        "skipping column {} of tableset {}: {}",
+                        column_name, tableset.basename, e
+                    );
+                    continue;
+                }
+            };
+            let order_key_position = sorting_key
+                .iter()
+                .position(|key_column| key_column == column_name)
+                .map(|pos| pos as u8);
+
+            if datatype.is_temporal() && partition_key_references(&partition_key, column_name) {
+                partition_by.push(column_name.clone());
+            }
+            let column = SimpleColumn::new(datatype, order_key_position, None, nullable);
+            builder = builder.add_column(
+                column_name,
+                match aggregation {
+                    Some(agg_method) => ColumnDefinition::WithAggregation(column, agg_method),
+                    None => ColumnDefinition::Simple(column),
+                },
+            );
+        }
+        if !partition_by.is_empty() {
+            partition_by.sort_unstable();
+            builder = builder.try_partition_by(partition_by)?;
+        }
+        builder.build()
+    }
+}
+
+/// parse a clickhouse column type like `Nullable(DateTime64(3))`
+///
+/// The `LowCardinality` wrapper is an encoding detail and `FixedString` columns are read
+/// as strings, so both are reconstructed as [`ClickhouseDataType::String`].
+fn parse_column_type(column_type: &str) -> Result<(ClickhouseDataType, bool), Error> {
+    let column_type_unwrapped = strip_type_wrapper(column_type, "LowCardinality");
+    let inner_type = strip_type_wrapper(column_type_unwrapped, "Nullable");
+    let nullable = inner_type.len() != column_type_unwrapped.len();
+
+    // type parameters like the precision of DateTime64 or timezones are not part of the schema
+    let datatype_name = inner_type.split('(').next().unwrap_or_default().trim();
+    let datatype = match datatype_name {
+        "UInt8" => ClickhouseDataType::UInt8,
+        "Int8" => ClickhouseDataType::Int8,
+        "UInt16" => ClickhouseDataType::UInt16,
+        "Int16" => ClickhouseDataType::Int16,
+        "UInt32" => ClickhouseDataType::UInt32,
+        "Int32" => ClickhouseDataType::Int32,
+        "UInt64" => ClickhouseDataType::UInt64,
+        "Int64" => ClickhouseDataType::Int64,
+        "Float32" => ClickhouseDataType::Float32,
+        "Float64" => ClickhouseDataType::Float64,
+        "Date" => ClickhouseDataType::Date,
+        "DateTime" => ClickhouseDataType::DateTime,
+        "DateTime64" => ClickhouseDataType::DateTime64,
+        "String" | "FixedString" => ClickhouseDataType::String,
+        _ => {
+            return Err(Error::SchemaValidationError(
+                type_name::<ClickhouseDataType>(),
+                format!("unsupported datatype: {}", column_type),
+            ))
+        }
+    };
+    Ok((datatype, nullable))
+}
+
+/// the aggregation of a `SimpleAggregateFunction(max, ...)` column type and its inner type.
+///
+/// Other column types are returned unchanged.
+fn parse_simple_aggregation(column_type: &str) -> (Option<AggregationMethod>, &str) {
+    let inner = strip_type_wrapper(column_type, "SimpleAggregateFunction");
+    match inner.split_once(',') {
+        Some((function, inner_type)) if inner.len() != column_type.len() => match function.trim() {
+            "max" => (Some(AggregationMethod::Max), inner_type.trim()),
+            "min" => (Some(AggregationMethod::Min), inner_type.trim()),
+            _ => (None, column_type),
+        },
+        _ => (None, column_type),
+    }
+}
+
+/// strip a type wrapper like `Nullable(...)`. Returns the input when the wrapper is not present.
+fn strip_type_wrapper<'a>(column_type: &'a str, wrapper: &str) -> &'a str {
+    column_type
+        .strip_prefix(wrapper)
+        .and_then(|t| t.strip_prefix('('))
+        .and_then(|t| t.strip_suffix(')'))
+        .unwrap_or(column_type)
+}
+
+static RE_ENGINE_PARAMS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(Replicated)?[a-zA-Z]+MergeTree(\(([^)]*)\))?").unwrap());
+
+/// parse the table engine from the `engine_full` column of `system.tables`
+fn parse_table_engine(engine_full: &str) -> Result<TableEngine, Error> {
+    let engine_name = engine_full
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    let params = || -> Vec<String> {
+        let params = RE_ENGINE_PARAMS
+            .captures(engine_full)
+            .and_then(|captures| captures.get(3))
+            .map(|params| params.as_str())
+            .unwrap_or_default();
+        split_key_expression(params)
+            .into_iter()
+            // the zookeeper path and the replica name of replicated tables are quoted strings
+            .filter(|param| !param.starts_with('\''))
+            .collect()
+    };
+    match engine_name
+        .strip_prefix("Replicated")
+        .unwrap_or(engine_name)
+    {
+        "ReplacingMergeTree" => Ok(TableEngine::ReplacingMergeTree(params().into_iter().next())),
+        "AggregatingMergeTree" => Ok(TableEngine::AggregatingMergeTree),
+        "SummingMergeTree" => {
+            let mut sum_columns = params();
+            sum_columns.sort_unstable();
+            Ok(TableEngine::SummingMergeTree(sum_columns))
+        }
+        _ => Err(Error::SchemaValidationError(
+            type_name::<TableEngine>(),
+            format!("unsupported table engine: {}", engine_full),
+        )),
+    }
+}
+
+/// split comma-separated expressions like the `sorting_key` of `system.tables`
+fn split_key_expression(expression: &str) -> Vec<String> {
+    expression
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .map(|part| part.trim().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+fn partition_key_references(partition_key: &str, column_name: &str) -> bool {
+    column_name != COL_NAME_H3INDEX
+        && partition_key
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| word == column_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use ukis_clickhouse_arrow_grpc::mock::MockClient;
+
+    use crate::clickhouse::compacted_tables::schema::{
+        AggregationMethod, ClickhouseDataType, ColumnDefinition, CompactedTableSchema,
+        SimpleColumn, TableEngine,
+    };
+
+    use super::{parse_column_type, parse_simple_aggregation, parse_table_engine};
+
+    #[test]
+    fn test_parse_column_type() {
+        assert_eq!(
+            parse_column_type("Float32").unwrap(),
+            (ClickhouseDataType::Float32, false)
+        );
+        assert_eq!(
+            parse_column_type("Nullable(UInt8)").unwrap(),
+            (ClickhouseDataType::UInt8, true)
+        );
+        assert_eq!(
+            parse_column_type("DateTime64(3, 'UTC')").unwrap(),
+            (ClickhouseDataType::DateTime64, false)
+        );
+        assert!(parse_column_type("Array(UInt8)").is_err());
+    }
+
+    #[test]
+    fn test_parse_simple_aggregation() {
+        assert_eq!(
+            parse_simple_aggregation("SimpleAggregateFunction(max, Nullable(UInt32))"),
+            (Some(AggregationMethod::Max), "Nullable(UInt32)")
+        );
+        assert_eq!(
+            parse_simple_aggregation("SimpleAggregateFunction(min, Float64)"),
+            (Some(AggregationMethod::Min), "Float64")
+        );
+        assert_eq!(
+            parse_simple_aggregation("SimpleAggregateFunction(any, Float64)"),
+            (None, "SimpleAggregateFunction(any, Float64)")
+        );
+        assert_eq!(parse_simple_aggregation("UInt32"), (None, "UInt32"));
+    }
+
+    #[test]
+    fn test_parse_string_column_types() {
+        for (column_type, nullable) in [
+            ("String", false),
+            ("Nullable(String)", true),
+            ("FixedString(2)", false),
+            ("Nullable(FixedString(3))", true),
+            ("LowCardinality(String)", false),
+            ("LowCardinality(Nullable(String))", true),
+        ] {
+            assert_eq!(
+                parse_column_type(column_type).unwrap(),
+                (ClickhouseDataType::String, nullable),
+                "{}",
+                column_type
+            );
+        }
+        assert!(parse_column_type("LowCardinality(Array(String))").is_err());
+    }
+
+    #[test]
+    fn test_parse_table_engine() {
+        assert_eq!(
+            parse_table_engine("ReplacingMergeTree PARTITION BY x ORDER BY h3index").unwrap(),
+            TableEngine::ReplacingMergeTree(None)
+        );
+        assert_eq!(
+            parse_table_engine(
+                "ReplicatedReplacingMergeTree('/clickhouse/t', '{replica}', version) ORDER BY h3index"
+            )
+            .unwrap(),
+            TableEngine::ReplacingMergeTree(Some("version".to_string()))
+        );
+        assert_eq!(
+            parse_table_engine("SummingMergeTree(b, a) PARTITION BY x ORDER BY h3index").unwrap(),
+            TableEngine::SummingMergeTree(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(
+            parse_table_engine(
+                "ReplicatedSummingMergeTree('/clickhouse/t', '{replica}', a) ORDER BY h3index"
+            )
+            .unwrap(),
+            TableEngine::SummingMergeTree(vec!["a".to_string()])
+        );
+        assert!(parse_table_engine("Memory").is_err());
+    }
+
+    #[tokio::test]
+    async fn schema_from_clickhouse() {
+        let mut client = MockClient::new();
+        client
+            .respond_with_dataframe(
+                "select table from system.columns",
+                DataFrame::new(vec![Series::new(
+                    "table",
+                    &[
+                        "elephants_04_base",
+                        "elephants_05_base",
+                        "elephants_04_compacted",
+                    ],
+                )])
+                .unwrap(),
+            )
+            .respond_with_dataframe(
+                "select name, type, count(*) as c",
+                DataFrame::new(vec![
+                    Series::new("name", &["elephant_count", "observed_on", "geometry"]),
+                    Series::new("type", &["UInt32", "Nullable(DateTime)", "Array(Float64)"]),
+                    Series::new("c", &[3u64, 3, 3]),
+                ])
+                .unwrap(),
+            )
+            .respond_with_dataframe(
+                "from system.tables",
+                DataFrame::new(vec![
+                    Series::new(
+                        "engine_full",
+                        &["SummingMergeTree(elephant_count) PARTITION BY (intDiv(h3index, 10), toYYYYMM(observed_on)) ORDER BY (h3index, observed_on)"],
+                    ),
+                    Series::new("sorting_key", &["h3index, observed_on"]),
+                    Series::new("partition_key", &["(intDiv(h3index, 10), toYYYYMM(observed_on))"]),
+                ])
+                .unwrap(),
+            );
+
+        let schema = CompactedTableSchema::from_clickhouse(&mut client, "db", "elephants")
+            .await
+            .unwrap();
+        assert!(client.queries()[2].contains("name = 'elephants_05_base'"));
+
+        assert_eq!(schema.name, "elephants");
+        assert_eq!(schema.h3_base_resolutions, vec![4, 5]);
+        assert_eq!(schema.max_h3_resolution().unwrap(), 5);
+        assert!(schema.use_compaction);
+        assert_eq!(
+            schema.table_engine,
+            TableEngine::SummingMergeTree(vec!["elephant_count".to_string()])
+        );
+        assert_eq!(schema.partition_by_columns, vec!["observed_on".to_string()]);
+
+        // the unsupported geometry column is skipped
+        assert_eq!(schema.columns.len(), 3);
+        assert_eq!(
+            schema.columns.get("observed_on"),
+            Some(&ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::DateTime,
+                Some(1),
+                None,
+                true
+            )))
+        );
+        assert_eq!(
+            schema.columns.get("elephant_count"),
+            Some(&ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::UInt32,
+                None,
+                None,
+                false
+            )))
+        );
+    }
+}
@@ -1,8 +1,10 @@
-use tracing::error;
+use tracing::{debug, error};
 
 use h3ron::collections::{HashMap, HashSet};
 use h3ron::iter::change_resolution;
 use h3ron::{H3Cell, Index};
+use itertools::Itertools;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 
 use crate::clickhouse::compacted_tables::{TableSet, COL_NAME_H3INDEX};
 use crate::Error;
@@ -20,16 +22,52 @@ pub enum TableSetQuery {
     /// * "<[table]>": will be filled with the table to be queried
     /// * "<[h3indexes]>": will be filled with an array of h3indexes used for the query. This should
     ///
+    /// Optional placeholders:
+    /// * "<[final]>": will be filled with `FINAL` when the `final_modifier` of the query
+    ///    is set, otherwise it gets removed. Must be placed directly after the table.
+    ///
     /// TODO: parsing and validating and injecting missing column into the query with https://github.com/ballista-compute/sqlparser-rs
     ///    would be nice, but as the parser does not implement a clickhouse dialect, its is probably more
     ///    error prone than it is beneficial.
     TemplatedSelect(String),
+
+    /// limit the number of rows the wrapped query fetches from each of the queried tables.
+    ///
+    /// The total number of rows returned is at most the limit multiplied with the number of
+    /// tables queried.
+    Limited(Box<TableSetQuery>, u64),
 }
 
 impl TableSetQuery {
+    /// limit the number of rows fetched from each table to `n`.
+    pub fn with_limit(self, n: u64) -> Self {
+        match self {
+            // only the innermost query needs to be kept as the smaller limit wins anyways
+            Self::Limited(inner, limit) => Self::Limited(inner, limit.min(n)),
+            query => Self::Limited(Box::new(query), n),
+        }
+    }
+
+    /// the query without any limits and the smallest limit wrapping it
+    fn split_limit(&self) -> (&Self, Option<u64>) {
+        let mut query = self;
+        let mut limit: Option<u64> = None;
+        while let Self::Limited(inner, inner_limit) = query {
+            limit = Some(limit.map_or(*inner_limit, |l| l.min(*inner_limit)));
+            query = inner.as_ref();
+        }
+        (query, limit)
+    }
+
     pub fn validate(&self) -> Result<(), Error> {
         match self {
             TableSetQuery::AutoGenerated => Ok(()),
+            TableSetQuery::Limited(inner, limit) => {
+                if *limit == 0 {
+                    return Err(Error::InvalidQueryLimit(*limit));
+                }
+                inner.validate()
+            }
             TableSetQuery::TemplatedSelect(querystring) => {
                 // validate only mandatory placeholders
                 for placeholder in &["<[table]>", "<[h3indexes]>"] {
@@ -67,6 +105,7 @@ pub trait BuildCellQueryString {
         tableset: &TableSet,
         h3_resolution: u8,
         h3cells: &[H3Cell],
+        final_modifier: bool,
     ) -> Result<String, Error>;
 }
 
@@ -76,6 +115,7 @@ impl BuildCellQueryString for TableSetQuery {
         tableset: &TableSet,
         h3_resolution: u8,
         h3cells: &[H3Cell],
+        final_modifier: bool,
     ) -> Result<String, Error> {
         if !tableset.base_tables.contains_key(&h3_resolution) {
             error!(
@@ -88,6 +128,13 @@ impl BuildCellQueryString for TableSetQuery {
             return Err(Error::EmptyCells);
         };
         self.validate()?;
+        let (query, limit) = self.split_limit();
+        if let TableSetQuery::TemplatedSelect(query_string) = query {
+            if final_modifier && !query_string.contains("<[final]>") {
+                return Err(Error::MissingQueryPlaceholder("<[final]>".to_string()));
+            }
+        }
+        let final_clause = if final_modifier { " FINAL" } else { "" };
 
         // collect the indexes and the parents (where the tables exist)
         let queryable_h3indexes = collect_queryable_h3indexes(tableset, h3cells, h3_resolution)?;
@@ -107,56 +154,293 @@ impl BuildCellQueryString for TableSetQuery {
         let mut query_string_parts = Vec::new();
 
         for table in tableset.tables_to_satisfy_query_at_resolution(h3_resolution)? {
-            if let Some(query_h3indexes) = queryable_h3indexes.get(&table.spec.h3_resolution) {
-                let query_h3indexesarray_string = format!(
-                    "[{}]",
-                    itertools::join(query_h3indexes.iter().map(|hi| hi.to_string()), ",",)
-                );
-                let tablename = table.to_table_name();
-                let qs = match self {
-                    TableSetQuery::AutoGenerated => {
+            let query_h3indexes = match queryable_h3indexes.get(&table.spec.h3_resolution) {
+                Some(query_h3indexes) if !query_h3indexes.is_empty() => query_h3indexes,
+                _ => {
+                    debug!(
+                        "no queryable h3indexes for table {} - omitting it from the query",
+                        table.to_table_name()
+                    );
+                    continue;
+                }
+            };
+            let query_h3indexesarray_string = format!(
+                "[{}]",
+                itertools::join(query_h3indexes.iter().map(|hi| hi.to_string()), ",",)
+            );
+            let tablename = table.to_table_name();
+            let qs = match query {
+                TableSetQuery::AutoGenerated => {
+                    format!(
+                        "select {}, {} from {}{} where {} in {}",
+                        COL_NAME_H3INDEX,
+                        selectable_columns,
+                        tablename,
+                        final_clause,
+                        COL_NAME_H3INDEX,
+                        query_h3indexesarray_string
+                    )
+                }
+                TableSetQuery::TemplatedSelect(query_string) => query_string
+                    .replace("<[table]>", &tablename)
+                    .replace("<[h3indexes]>", &query_h3indexesarray_string)
+                    .replace("<[final]>", final_clause.trim_start()),
+                TableSetQuery::Limited(..) => unreachable!("limits are removed by split_limit"),
+            };
+            let qs = match limit {
+                Some(limit) => format!("select * from ({}) limit {}", qs, limit),
+                None => qs,
+            };
+            query_string_parts.push(qs);
+        }
+        Ok(itertools::join(query_string_parts.iter(), " union all "))
+    }
+}
+
+impl TableSet {
+    /// build a query counting the rows found for the given h3 cells.
+    ///
+    /// The query returns the columns `r` with the resolution of the table and `cnt` with the
+    /// number of rows found in that table. Like with [`BuildCellQueryString`] the parent cells
+    /// in the compacted tables are included, tables without any rows for the cells
+    /// are omitted from the results. Count queries do not require ClickHouse to read the
+    /// data columns and are much cheaper than fetching the actual data.
+    ///
+    /// All cells must use the same resolution, which is the resolution the tables are selected
+    /// for. Fails with [`Error::MixedH3Resolutions`] otherwise, cells of different resolutions
+    /// have to be counted using separate queries.
+    pub fn build_count_query(&self, h3cells: &[H3Cell]) -> Result<String, Error> {
+        let h3_resolution = match h3cells.first() {
+            Some(cell) => cell.resolution(),
+            None => return Err(Error::EmptyCells),
+        };
+        if h3cells
+            .iter()
+            .any(|cell| cell.resolution() != h3_resolution)
+        {
+            return Err(Error::MixedH3Resolutions);
+        }
+
+        let queryable_h3indexes = collect_queryable_h3indexes(self, h3cells, h3_resolution)?;
+        let query_string_parts = self
+            .tables_to_satisfy_query_at_resolution(h3_resolution)?
+            .iter()
+            .filter_map(|table| {
+                queryable_h3indexes
+                    .get(&table.spec.h3_resolution)
+                    .map(|query_h3indexes| {
                         format!(
-                            "select {}, {} from {} where {} in {}",
-                            COL_NAME_H3INDEX,
-                            selectable_columns,
-                            tablename,
+                            "select {} as r, count() as cnt from {} where {} in [{}] group by r",
+                            table.spec.h3_resolution,
+                            table.to_table_name(),
                             COL_NAME_H3INDEX,
-                            query_h3indexesarray_string
+                            itertools::join(query_h3indexes.iter().map(|hi| hi.to_string()), ",")
                         )
-                    }
-                    TableSetQuery::TemplatedSelect(query_string) => query_string
-                        .replace("<[table]>", &tablename)
-                        .replace("<[h3indexes]>", &query_h3indexesarray_string),
-                };
-                query_string_parts.push(qs);
-            }
+                    })
+            })
+            .collect::<Vec<_>>();
+        if query_string_parts.is_empty() {
+            return Err(Error::NoQueryableTables);
         }
         Ok(itertools::join(query_string_parts.iter(), " union all "))
     }
 }
 
 /// collect the indexes and the parents (where the tables exist)
+///
+/// The resolutions are processed in parallel as large numbers of cells can be involved.
 fn collect_queryable_h3indexes(
     tableset: &TableSet,
     cells: &[H3Cell],
     query_h3_resolution: u8,
 ) -> Result<HashMap<u8, HashSet<u64>>, Error> {
-    let mut queryable_h3indexes: HashMap<_, HashSet<_>> = tableset
+    let resolutions: Vec<_> = tableset
         .base_tables
-        .iter()
-        .chain(tableset.compacted_tables.iter())
-        .filter(|(r, _)| **r <= query_h3_resolution)
-        .map(|(r, _)| (*r, HashSet::default()))
+        .keys()
+        .chain(tableset.compacted_tables.keys())
+        .filter(|r| **r <= query_h3_resolution)
+        .copied()
+        .unique()
         .collect();
 
-    for (resolution, queryable_h3indexes_set) in queryable_h3indexes.iter_mut() {
-        let mut h3indexes_at_resolution = change_resolution(cells, *resolution)
-            .map(|cell_res| cell_res.map(|cell| cell.h3index()))
-            .collect::<Result<Vec<_>, _>>()?;
-        h3indexes_at_resolution.sort_unstable();
-        h3indexes_at_resolution.dedup();
+    resolutions
+        .into_par_iter()
+        .map(|resolution| -> Result<(u8, HashSet<u64>), Error> {
+            let mut h3indexes_at_resolution = change_resolution(cells, resolution)
+                .map(|cell_res| cell_res.map(|cell| cell.h3index()))
+                .collect::<Result<Vec<_>, _>>()?;
+            h3indexes_at_resolution.sort_unstable();
+            h3indexes_at_resolution.dedup();
+
+            Ok((
+                resolution,
+                h3indexes_at_resolution.into_iter().collect::<HashSet<_>>(),
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|queryable_h3indexes| queryable_h3indexes.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use geo_types::Coord;
+    use h3ron::{H3Cell, Index};
+
+    use crate::clickhouse::compacted_tables::select::{BuildCellQueryString, TableSetQuery};
+    use crate::clickhouse::compacted_tables::tableset::find_tablesets;
+    use crate::clickhouse::compacted_tables::TableSet;
+    use crate::Error;
+
+    fn elephants_tableset() -> TableSet {
+        let mut tableset = find_tablesets(["elephants_04_compacted", "elephants_05_base"])
+            .remove("elephants")
+            .unwrap();
+        tableset
+            .columns
+            .insert("elephant_count".to_string(), "UInt32".to_string());
+        tableset
+    }
+
+    #[test]
+    fn build_count_query() {
+        let tableset = elephants_tableset();
+        let cell = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 5).unwrap();
+
+        let query_string = tableset.build_count_query(&[cell]).unwrap();
+        assert!(query_string.contains(&format!(
+            "select 5 as r, count() as cnt from elephants_05_base where h3index in [{}] group by r",
+            cell.h3index()
+        )));
+        assert!(query_string.contains(&format!(
+            "select 4 as r, count() as cnt from elephants_04_compacted where h3index in [{}] group by r",
+            cell.get_parent(4).unwrap().h3index()
+        )));
+        assert!(!query_string.contains("elephant_count"));
+
+        assert!(matches!(
+            tableset.build_count_query(&[]),
+            Err(Error::EmptyCells)
+        ));
+        assert!(matches!(
+            tableset.build_count_query(&[cell, cell.get_parent(4).unwrap()]),
+            Err(Error::MixedH3Resolutions)
+        ));
+        assert!(matches!(
+            tableset.build_count_query(&[cell.get_parent(4).unwrap()]),
+            Err(Error::UnsupportedH3Resolution(4))
+        ));
+    }
+
+    #[test]
+    fn query_at_resolution_0() {
+        let mut tableset = find_tablesets([
+            "elephants_00_compacted",
+            "elephants_00_base",
+            "elephants_01_base",
+        ])
+        .remove("elephants")
+        .unwrap();
+        tableset
+            .columns
+            .insert("elephant_count".to_string(), "UInt32".to_string());
+        let cell = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 0).unwrap();
+
+        let query_string = TableSetQuery::AutoGenerated
+            .build_cell_query_string(&tableset, 0, &[cell], false)
+            .unwrap();
+        let mut parts: Vec<_> = query_string.split(" union all ").collect();
+        parts.sort_unstable();
+        let expected: Vec<_> = ["elephants_00_base", "elephants_00_compacted"]
+            .iter()
+            .map(|table_name| {
+                format!(
+                    "select h3index, elephant_count from {} where h3index in [{}]",
+                    table_name,
+                    cell.h3index()
+                )
+            })
+            .collect();
+        assert_eq!(parts, expected);
+    }
+
+    #[test]
+    fn limited_query() {
+        let tableset = elephants_tableset();
+        let cells = vec![H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 5).unwrap()];
+
+        let query = TableSetQuery::AutoGenerated.with_limit(100);
+        query.validate().unwrap();
+        let query_string = query
+            .build_cell_query_string(&tableset, 5, &cells, false)
+            .unwrap();
+        let parts: Vec<_> = query_string.split(" union all ").collect();
+        assert_eq!(parts.len(), 2);
+        for part in parts {
+            assert!(
+                part.starts_with("select * from (select h3index, elephant_count from elephants_0")
+            );
+            assert!(part.ends_with(") limit 100"));
+        }
+
+        let templated = TableSetQuery::TemplatedSelect(
+            "select h3index from <[table]> <[final]> where h3index in <[h3indexes]>".to_string(),
+        )
+        .with_limit(20)
+        .with_limit(50);
+        let query_string = templated
+            .build_cell_query_string(&tableset, 5, &cells, true)
+            .unwrap();
+        assert!(query_string.contains("from elephants_05_base FINAL where"));
+        assert_eq!(query_string.matches(") limit 20").count(), 2);
+
+        assert!(matches!(
+            TableSetQuery::AutoGenerated.with_limit(0).validate(),
+            Err(Error::InvalidQueryLimit(0))
+        ));
+        assert!(matches!(
+            TableSetQuery::TemplatedSelect("select 1".to_string())
+                .with_limit(10)
+                .validate(),
+            Err(Error::MissingQueryPlaceholder(_))
+        ));
+    }
+
+    #[test]
+    fn final_modifier() {
+        let tableset = elephants_tableset();
+        let cells = vec![H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 5).unwrap()];
+
+        let query_string = TableSetQuery::AutoGenerated
+            .build_cell_query_string(&tableset, 5, &cells, true)
+            .unwrap();
+        assert!(query_string.contains("from elephants_05_base FINAL where"));
+        assert!(query_string.contains("from elephants_04_compacted FINAL where"));
+
+        let query_string = TableSetQuery::AutoGenerated
+            .build_cell_query_string(&tableset, 5, &cells, false)
+            .unwrap();
+        assert!(!query_string.contains("FINAL"));
+        assert!(query_string.contains("from elephants_05_base where"));
+
+        let templated = TableSetQuery::TemplatedSelect(
+            "select h3index from <[table]> <[final]> where h3index in <[h3indexes]>".to_string(),
+        );
+        let query_string = templated
+            .build_cell_query_string(&tableset, 5, &cells, true)
+            .unwrap();
+        assert!(query_string.contains("from elephants_05_base FINAL where"));
+        let query_string = templated
+            .build_cell_query_string(&tableset, 5, &cells, false)
+            .unwrap();
+        assert!(!query_string.contains("FINAL"));
 
-        queryable_h3indexes_set.extend(h3indexes_at_resolution.iter());
+        // templates without the placeholder can not be used with FINAL
+        assert!(matches!(
+            TableSetQuery::TemplatedSelect(
+                "select h3index from <[table]> where h3index in <[h3indexes]>".to_string()
+            )
+            .build_cell_query_string(&tableset, 5, &cells, true),
+            Err(Error::MissingQueryPlaceholder(_))
+        ));
     }
-    Ok(queryable_h3indexes)
 }
@@ -2,10 +2,14 @@ use async_trait::async_trait;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::any::type_name;
 use std::borrow::Cow;
+use tracing::warn;
 
 use h3ron::collections::HashMap;
+use h3ron::H3_MAX_RESOLUTION;
 
+use crate::clickhouse::compacted_tables::schema::CompactedTableSchema;
 use crate::clickhouse::compacted_tables::CompactedTablesStore;
 use crate::Error;
 
@@ -26,6 +30,41 @@ impl TableSpec {
     pub fn is_temporary(&self) -> bool {
         self.temporary_key.is_some()
     }
+
+    /// check that the spec describes a table which can exist. H3 only supports the
+    /// resolutions 0 to 15, temporary keys must be usable within table names and compacted
+    /// tables are always named with their `_compacted` suffix.
+    pub fn is_valid(&self) -> Result<(), Error> {
+        if self.h3_resolution > H3_MAX_RESOLUTION {
+            return Err(Error::UnsupportedH3Resolution(self.h3_resolution));
+        }
+        if let Some(temporary_key) = &self.temporary_key {
+            if temporary_key.is_empty()
+                || !temporary_key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(Error::SchemaValidationError(
+                    type_name::<Self>(),
+                    format!("invalid temporary key: \"{}\"", temporary_key),
+                ));
+            }
+        }
+        if self.is_compacted && !self.has_base_suffix {
+            return Err(Error::SchemaValidationError(
+                type_name::<Self>(),
+                "compacted tables require the table name suffix".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// check if both specs describe the same logical table, ignoring the `temporary_key`
+    pub fn is_compatible_with(&self, other: &TableSpec) -> bool {
+        self.h3_resolution == other.h3_resolution
+            && self.is_compacted == other.is_compacted
+            && self.has_base_suffix == other.has_base_suffix
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -35,17 +74,23 @@ pub struct Table<'a> {
 }
 
 static RE_TABLE: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"^([a-zA-Z].[a-zA-Z_0-9]+)_([0-9]{2})(_(base|compacted))?(_tmp([a-zA-Z0-9_]+))?$")
-        .unwrap()
+    // only matching the valid resolutions keeps numbers in temporary keys from being
+    // mistaken for the resolution
+    Regex::new(
+        r"^([a-zA-Z][a-zA-Z_0-9]*)_(0[0-9]|1[0-5])(_(base|compacted))?(_tmp([a-zA-Z0-9_]+))?$",
+    )
+    .unwrap()
 });
 
 impl<'a> Table<'a> {
-    /// TODO: this should return an error? at least no unwrapping would be nice
+    /// parse a table name. Returns `None` for names not following the naming scheme
+    /// of the tablesets and for invalid table specs.
     pub fn parse(full_table_name: &str) -> Option<Self> {
-        RE_TABLE.captures(full_table_name).map(|captures| Table {
+        let captures = RE_TABLE.captures(full_table_name)?;
+        let table = Table {
             basename: captures[1].to_string().into(),
             spec: TableSpec {
-                h3_resolution: captures[2].parse().unwrap(),
+                h3_resolution: captures[2].parse().ok()?,
                 is_compacted: if let Some(suffix) = captures.get(4) {
                     suffix.as_str() == "compacted"
                 } else {
@@ -54,7 +99,9 @@ impl<'a> Table<'a> {
                 temporary_key: captures.get(6).map(|mtch| mtch.as_str().to_string()),
                 has_base_suffix: captures.get(4).is_some(),
             },
-        })
+        };
+        table.spec.is_valid().ok()?;
+        Some(table)
     }
 
     pub fn to_table_name(&self) -> String {
@@ -81,6 +128,16 @@ impl<'a> Table<'a> {
         )
     }
 
+    /// all non-temporary tables of the tableset described by the `schema`, in the order the
+    /// `CREATE TABLE` statements of the schema are generated in.
+    pub fn all_for_schema(schema: &'a CompactedTableSchema) -> Result<Vec<Self>, Error> {
+        Ok(schema
+            .get_resolution_metadata()?
+            .iter()
+            .map(|resolution_metadata| schema.build_table(resolution_metadata, &None))
+            .collect())
+    }
+
     fn from_tablespec(basename: &'a str, spec: &TableSpec) -> Self {
         Self {
             basename: basename.into(),
@@ -101,6 +158,10 @@ pub struct TableSet {
     pub columns: HashMap<String, String>,
     pub base_tables: HashMap<u8, TableSpec>,
     pub compacted_tables: HashMap<u8, TableSpec>,
+
+    /// temporary tables of unfinished or failed inserts. Only populated by
+    /// [`find_all_tablesets`] when including temporary tables.
+    pub temporary_tables: Vec<TableSpec>,
 }
 
 impl TableSet {
@@ -110,6 +171,7 @@ impl TableSet {
             compacted_tables: Default::default(),
             base_tables: Default::default(),
             columns: Default::default(),
+            temporary_tables: Default::default(),
         }
     }
 
@@ -149,6 +211,19 @@ impl TableSet {
         self.base_tables.len() + self.compacted_tables.len()
     }
 
+    /// check if data at `h3_resolution` can be queried from this tableset.
+    ///
+    /// This is a cheap check in advance of building queries. Queries require a base table at
+    /// the resolution, the compacted tables of coarser resolutions are included when present.
+    pub fn covers_resolution(&self, h3_resolution: u8) -> bool {
+        self.base_tables.contains_key(&h3_resolution)
+    }
+
+    /// the finest resolution data can be queried at
+    pub fn max_queryable_resolution(&self) -> Option<u8> {
+        self.base_tables.keys().max().copied()
+    }
+
     /// tables to fetch to satisfy a query for the data at `h3_resolution`
     pub fn tables_to_satisfy_query_at_resolution(
         &self,
@@ -215,29 +290,57 @@ where
     }
 }
 
-/// identify the tablesets from a slice of tablenames
-pub(crate) fn find_tablesets<T: AsRef<str>>(tablenames: &[T]) -> HashMap<String, TableSet> {
+/// identify the tablesets from the given tablenames.
+///
+/// Tablenames not following the naming scheme of the tablesets as well as temporary
+/// tables are ignored.
+pub fn find_tablesets(
+    tablenames: impl IntoIterator<Item = impl AsRef<str>>,
+) -> HashMap<String, TableSet> {
+    find_all_tablesets(tablenames, false)
+}
+
+/// identify the tablesets from the given tablenames like [`find_tablesets`].
+///
+/// With `include_temporary` the temporary tables are collected in the `temporary_tables` of
+/// the tableset they belong to. This allows inspecting the state of running inserts or
+/// cleaning up after failed ones.
+pub fn find_all_tablesets(
+    tablenames: impl IntoIterator<Item = impl AsRef<str>>,
+    include_temporary: bool,
+) -> HashMap<String, TableSet> {
     let mut tablesets = HashMap::default();
 
-    for tablename in tablenames.iter() {
+    for tablename in tablenames {
         if let Some(table) = Table::parse(tablename.as_ref()) {
-            if table.spec.is_temporary() {
-                // ignore temporary tables here for now
+            if table.spec.is_temporary() && !include_temporary {
                 continue;
             }
 
             let tableset = tablesets
                 .entry(table.basename.to_string())
                 .or_insert_with(|| TableSet::new(&table.basename));
-            if table.spec.is_compacted {
-                tableset
-                    .compacted_tables
-                    .insert(table.spec.h3_resolution, table.spec);
+            if table.spec.is_temporary() {
+                tableset.temporary_tables.push(table.spec);
+                continue;
+            }
+            let tables = if table.spec.is_compacted {
+                &mut tableset.compacted_tables
             } else {
-                tableset
-                    .base_tables
-                    .insert(table.spec.h3_resolution, table.spec);
+                &mut tableset.base_tables
+            };
+            if let Some(existing_spec) = tables.get(&table.spec.h3_resolution) {
+                if !existing_spec.is_compatible_with(&table.spec) {
+                    warn!(
+                        "tableset {} contains conflicting tables for resolution {}: {} and {}. Using the latter",
+                        table.basename,
+                        table.spec.h3_resolution,
+                        Table::from_tablespec(&table.basename, existing_spec).to_table_name(),
+                        table.to_table_name()
+                    );
+                }
             }
+            tables.insert(table.spec.h3_resolution, table.spec);
         }
     }
     tablesets
@@ -245,8 +348,55 @@ pub(crate) fn find_tablesets<T: AsRef<str>>(tablenames: &[T]) -> HashMap<String,
 
 #[cfg(test)]
 mod tests {
-    use crate::clickhouse::compacted_tables::tableset::{find_tablesets, Table, TableSpec};
+    use proptest::prelude::*;
+
+    use crate::clickhouse::compacted_tables::schema::CompactedTableSchemaBuilder;
+    use crate::clickhouse::compacted_tables::tableset::{
+        find_all_tablesets, find_tablesets, Table, TableSpec,
+    };
     use crate::clickhouse::compacted_tables::temporary_key::TemporaryKey;
+    use crate::Error;
+
+    #[test]
+    fn test_table_all_for_schema() {
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![1, 2])
+            .build()
+            .unwrap();
+        let tables = Table::all_for_schema(&schema).unwrap();
+        let table_names: Vec<_> = tables.iter().map(|t| t.to_table_name()).collect();
+        assert_eq!(
+            table_names,
+            vec![
+                "elephants_01_base",
+                "elephants_02_base",
+                "elephants_00_compacted",
+                "elephants_01_compacted",
+                "elephants_02_compacted"
+            ]
+        );
+        assert!(tables.iter().all(|table| !table.spec.is_temporary()));
+
+        // same order as the generated DDL
+        let statements = schema.build_create_statements(&None).unwrap();
+        assert_eq!(statements.len(), table_names.len());
+        for (statement, table_name) in statements.iter().zip(table_names.iter()) {
+            assert!(statement.starts_with(&format!("CREATE TABLE IF NOT EXISTS {} (", table_name)));
+        }
+
+        let schema = CompactedTableSchemaBuilder::new("elephants")
+            .h3_base_resolutions(vec![3])
+            .use_compacted_resolutions(false)
+            .no_base_suffix()
+            .build()
+            .unwrap();
+        let table_names: Vec<_> = Table::all_for_schema(&schema)
+            .unwrap()
+            .iter()
+            .map(|t| t.to_table_name())
+            .collect();
+        assert_eq!(table_names, vec!["elephants_03"]);
+    }
 
     #[test]
     fn test_table_to_name() {
@@ -332,6 +482,277 @@ mod tests {
         assert_eq!(table_u.spec.temporary_key, Some("5t".to_string()));
     }
 
+    #[test]
+    fn test_table_parse_arbitrary_input() {
+        let fragments = [
+            "",
+            "_",
+            "a",
+            "Z9",
+            "_05",
+            "05",
+            "_5",
+            "_99",
+            "_base",
+            "_compacted",
+            "_tmp",
+            "_tmpx_1",
+            "\u{0}",
+            "ä",
+            "🐘",
+            " ",
+            "'",
+            "\n",
+            "_05_base_tmp",
+        ];
+        // combinations of fragments resembling table names must never panic
+        for f1 in fragments.iter() {
+            for f2 in fragments.iter() {
+                for f3 in fragments.iter() {
+                    let name = format!("{}{}{}", f1, f2, f3);
+                    if let Some(table) = Table::parse(&name) {
+                        assert_eq!(table.to_table_name(), name);
+                    }
+                }
+            }
+        }
+        assert!(Table::parse(&"a".repeat(10_000)).is_none());
+        assert!(Table::parse(&format!("{}_05", "a".repeat(10_000))).is_some());
+    }
+
+    proptest! {
+        #[test]
+        fn test_table_parse_serialize_parse(
+            name in "[a-zA-Z][a-zA-Z_0-9]{0,12}_(0[0-9]|1[0-5])(_base|_compacted)?(_tmp[a-zA-Z0-9_]{1,8})?"
+        ) {
+            let table = Table::parse(&name).unwrap();
+            let table_name = table.to_table_name();
+            prop_assert_eq!(&table_name, &name);
+            let reparsed = Table::parse(&table_name).unwrap();
+            prop_assert_eq!(reparsed.basename, table.basename);
+            prop_assert_eq!(reparsed.spec, table.spec);
+        }
+
+        #[test]
+        fn test_table_parse_arbitrary_strings(name in "\\PC*") {
+            if let Some(table) = Table::parse(&name) {
+                prop_assert_eq!(table.to_table_name(), name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_parse_basename_characters() {
+        assert!(Table::parse("a$_01_base").is_none());
+        assert!(Table::parse("a$_stuff_05_base").is_none());
+        assert!(Table::parse("_x_05").is_none());
+        assert!(Table::parse("5x_05").is_none());
+
+        let table = Table::parse("x_05").unwrap();
+        assert_eq!(table.basename, "x");
+        assert_eq!(table.spec.h3_resolution, 5);
+        assert!(!table.spec.has_base_suffix);
+    }
+
+    #[test]
+    fn test_table_name_roundtrip() {
+        let basenames = ["x", "t_", "ab", "a_05", "Some_Table_9", "x__0_base"];
+        let temporary_keys = [None, Some("5t"), Some("_a_1")];
+        for basename in basenames {
+            for h3_resolution in 0..=15 {
+                for (is_compacted, has_base_suffix) in [(false, false), (false, true), (true, true)]
+                {
+                    for temporary_key in temporary_keys {
+                        let table = Table {
+                            basename: basename.into(),
+                            spec: TableSpec {
+                                h3_resolution,
+                                is_compacted,
+                                temporary_key: temporary_key.map(|tk| tk.to_string()),
+                                has_base_suffix,
+                            },
+                        };
+                        let table_name = table.to_table_name();
+                        let parsed = Table::parse(&table_name).unwrap();
+                        assert_eq!(parsed.to_table_name(), table_name);
+                        assert_eq!(parsed.basename, table.basename);
+                        assert_eq!(parsed.spec, table.spec);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_table_parse_h3_resolution_range() {
+        assert_eq!(
+            Table::parse("some_table_15_base")
+                .unwrap()
+                .spec
+                .h3_resolution,
+            15
+        );
+        assert!(Table::parse("some_table_16_base").is_none());
+        assert!(Table::parse("some_table_99").is_none());
+
+        let table = Table::parse("some_table_05_tmp_99").unwrap();
+        assert_eq!(table.basename, "some_table");
+        assert_eq!(table.spec.h3_resolution, 5);
+        assert_eq!(table.spec.temporary_key.as_deref(), Some("_99"));
+
+        let mut spec = Table::parse("some_table_15_compacted").unwrap().spec;
+        assert!(spec.is_valid().is_ok());
+        spec.h3_resolution = 16;
+        assert!(matches!(
+            spec.is_valid(),
+            Err(Error::UnsupportedH3Resolution(16))
+        ));
+    }
+
+    #[test]
+    fn test_tablespec_is_valid_temporary_key() {
+        let mut spec = Table::parse("some_table_05_base_tmp5t").unwrap().spec;
+        assert!(spec.is_valid().is_ok());
+        spec.temporary_key = Some(TemporaryKey::new().to_table_suffix());
+        assert!(spec.is_valid().is_ok());
+
+        for invalid_key in ["", "5 t", "5t;drop", "5-t"] {
+            spec.temporary_key = Some(invalid_key.to_string());
+            assert!(matches!(
+                spec.is_valid(),
+                Err(Error::SchemaValidationError(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_tablespec_is_valid_compacted_suffix() {
+        let mut spec = Table::parse("some_table_05_compacted").unwrap().spec;
+        assert!(spec.is_valid().is_ok());
+        spec.has_base_suffix = false;
+        assert!(matches!(
+            spec.is_valid(),
+            Err(Error::SchemaValidationError(_, _))
+        ));
+
+        // base tables may omit the suffix
+        let spec = Table::parse("some_table_05").unwrap().spec;
+        assert!(!spec.has_base_suffix);
+        assert!(spec.is_valid().is_ok());
+    }
+
+    #[test]
+    fn test_tablespec_is_compatible_with() {
+        let spec = Table::parse("some_table_05_base").unwrap().spec;
+        assert!(spec.is_compatible_with(&Table::parse("some_table_05_base_tmp5t").unwrap().spec));
+        assert!(!spec.is_compatible_with(&Table::parse("some_table_05").unwrap().spec));
+        assert!(!spec.is_compatible_with(&Table::parse("some_table_05_compacted").unwrap().spec));
+        assert!(!spec.is_compatible_with(&Table::parse("some_table_06_base").unwrap().spec));
+    }
+
+    #[test]
+    fn test_find_tablesets_conflicting_suffixes() {
+        let tablesets = find_tablesets(["some_table_05_base", "some_table_05"]);
+        let tableset = tablesets.get("some_table").unwrap();
+        assert_eq!(tableset.base_resolutions(), vec![5]);
+        assert!(!tableset.base_tables[&5].has_base_suffix);
+    }
+
+    #[test]
+    fn test_covers_resolution() {
+        let tablesets = find_tablesets(
+            (0..=13)
+                .flat_map(|h3_resolution| {
+                    [
+                        format!("water_{:02}_base", h3_resolution),
+                        format!("water_{:02}_compacted", h3_resolution),
+                    ]
+                })
+                .chain(
+                    ["elephants_02", "elephants_03", "elephants_01_compacted"].map(String::from),
+                ),
+        );
+
+        let water_ts = tablesets.get("water").unwrap();
+        assert!((0..=13).all(|h3_resolution| water_ts.covers_resolution(h3_resolution)));
+        assert!(!water_ts.covers_resolution(14));
+        assert_eq!(water_ts.max_queryable_resolution(), Some(13));
+
+        let elephants_ts = tablesets.get("elephants").unwrap();
+        assert!(!elephants_ts.covers_resolution(1));
+        assert!(elephants_ts.covers_resolution(2));
+        assert!(elephants_ts.covers_resolution(3));
+        assert!(!elephants_ts.covers_resolution(4));
+        assert_eq!(elephants_ts.max_queryable_resolution(), Some(3));
+
+        // consistent with the tables used for queries
+        for h3_resolution in 0..=15 {
+            assert_eq!(
+                elephants_ts.covers_resolution(h3_resolution),
+                elephants_ts
+                    .tables_to_satisfy_query_at_resolution(h3_resolution)
+                    .is_ok()
+            );
+        }
+
+        let compacted_only = find_tablesets(["lions_04_compacted"]);
+        assert_eq!(
+            compacted_only
+                .get("lions")
+                .unwrap()
+                .max_queryable_resolution(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_all_tablesets_temporary() {
+        let table_names = [
+            "elephants_04_base",
+            "elephants_05_base",
+            "elephants_05_base_tmp5t",
+            "elephants_04_compacted_tmp5t",
+            "lions_06_base_tmpx",
+        ];
+
+        let tablesets = find_all_tablesets(table_names, false);
+        assert_eq!(tablesets.len(), 1);
+        assert!(tablesets["elephants"].temporary_tables.is_empty());
+
+        let tablesets = find_all_tablesets(table_names, true);
+        assert_eq!(tablesets.len(), 2);
+        let elephants_ts = &tablesets["elephants"];
+        assert_eq!(elephants_ts.base_resolutions(), vec![4, 5]);
+        assert!(elephants_ts.compacted_resolutions().is_empty());
+        let mut temporary_tables: Vec<_> = elephants_ts
+            .temporary_tables
+            .iter()
+            .map(|spec| Table::from_tablespec("elephants", spec).to_table_name())
+            .collect();
+        temporary_tables.sort_unstable();
+        assert_eq!(
+            temporary_tables,
+            vec!["elephants_04_compacted_tmp5t", "elephants_05_base_tmp5t"]
+        );
+
+        // tablesets consisting only of temporary tables
+        let lions_ts = &tablesets["lions"];
+        assert_eq!(lions_ts.num_tables(), 0);
+        assert_eq!(lions_ts.temporary_tables.len(), 1);
+    }
+
+    #[test]
+    fn test_find_tablesets_from_iterator() {
+        let tablesets = find_tablesets(
+            (4..=6)
+                .map(|h3_resolution| format!("some_table_{:02}_base", h3_resolution))
+                .chain(std::iter::once("some_table_05_compacted".to_string())),
+        );
+        let tableset = tablesets.get("some_table").unwrap();
+        assert_eq!(tableset.base_resolutions(), vec![4, 5, 6]);
+        assert_eq!(tableset.compacted_resolutions(), vec![5]);
+    }
+
     #[test]
     fn test_find_tablesets() {
         let table_names = [
@@ -413,7 +834,7 @@ mod tests {
             "elephants_01_compacted",
         ];
 
-        let tablesets = find_tablesets(&table_names);
+        let tablesets = find_tablesets(table_names);
         assert_eq!(tablesets.len(), 3);
         assert!(tablesets.contains_key("water"));
         let water_ts = tablesets.get("water").unwrap();
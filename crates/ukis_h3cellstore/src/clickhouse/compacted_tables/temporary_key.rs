@@ -21,15 +21,37 @@ impl TemporaryKey {
             random_part: rng.gen(),
         }
     }
-}
 
-impl ToString for TemporaryKey {
-    fn to_string(&self) -> String {
+    /// the key as used in the names of the temporary tables - following the `_tmp` of
+    /// the table name.
+    ///
+    /// Only consists of characters allowed by the table name parsing of [`crate::clickhouse::compacted_tables::Table`].
+    pub fn to_table_suffix(&self) -> String {
         format!(
             "{}_{}_{}",
             self.unix_timestamp_secs, self.unix_timestamp_millis, self.random_part
         )
     }
+
+    /// parse a key created by [`TemporaryKey::to_table_suffix`]
+    pub fn from_table_suffix(suffix: &str) -> Option<Self> {
+        let mut parts = suffix.split('_');
+        let key = Self {
+            unix_timestamp_secs: parts.next()?.parse().ok()?,
+            unix_timestamp_millis: parts.next()?.parse().ok()?,
+            random_part: parts.next()?.parse().ok()?,
+        };
+        if parts.next().is_some() || key.unix_timestamp_millis >= 1000 {
+            return None;
+        }
+        Some(key)
+    }
+}
+
+impl ToString for TemporaryKey {
+    fn to_string(&self) -> String {
+        self.to_table_suffix()
+    }
 }
 
 impl Default for TemporaryKey {
@@ -40,8 +62,35 @@ impl Default for TemporaryKey {
 
 #[cfg(test)]
 mod tests {
+    use crate::clickhouse::compacted_tables::Table;
+
     use super::TemporaryKey;
 
+    #[test]
+    fn temporary_key_table_suffix_roundtrip() {
+        let key = TemporaryKey::new();
+        assert_eq!(
+            TemporaryKey::from_table_suffix(&key.to_table_suffix()),
+            Some(key.clone())
+        );
+
+        // the suffix survives being a part of a table name
+        let table =
+            Table::parse(&format!("elephants_05_base_tmp{}", key.to_table_suffix())).unwrap();
+        assert_eq!(
+            table
+                .spec
+                .temporary_key
+                .and_then(|tk| TemporaryKey::from_table_suffix(&tk)),
+            Some(key)
+        );
+
+        assert!(TemporaryKey::from_table_suffix("5t").is_none());
+        assert!(TemporaryKey::from_table_suffix("1_2").is_none());
+        assert!(TemporaryKey::from_table_suffix("1_2_3_4").is_none());
+        assert!(TemporaryKey::from_table_suffix("1_1000_3").is_none());
+    }
+
     #[test]
     fn temporary_key_is_unique() {
         dbg!(TemporaryKey::new());
@@ -1,19 +1,24 @@
 use futures::Stream;
-use geo_types::Geometry;
+use geo_types::{Coord, Geometry};
 use h3ron::collections::{H3CellSet, RandomState};
 use h3ron::iter::change_resolution;
-use h3ron::{H3Cell, ToH3Cells};
+use h3ron::{H3Cell, ToCoordinate, ToH3Cells};
 use h3ron_polars::frame::H3DataFrame;
 use postage::prelude::{Sink, Stream as _};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::spawn;
 use tokio::task::spawn_blocking;
 use tracing::{debug, debug_span, info, Instrument};
-use ukis_clickhouse_arrow_grpc::Client;
+use ukis_clickhouse_arrow_grpc::ArrowInterface;
 
 use crate::clickhouse::compacted_tables::{
-    CompactedTablesStore, QueryOptions, TableSet, TableSetQuery,
+    CompactedTablesStore, QueryOptions, QueryPriority, TableSet, TableSetQuery,
 };
 use crate::Error;
 
@@ -86,6 +91,13 @@ pub struct TraversalOptions {
     ///
     /// Unit for the buffer is number of cells in reslution `h3_resolution`. No buffer is used per default.
     pub include_buffer: u32,
+
+    /// the order in which the traversal cells are visited.
+    pub sort_strategy: TraversalSortStrategy,
+
+    /// priority of the queries on the ClickHouse server. Setting this to `QueryPriority::Low`
+    /// lets the background loading of the traversal yield to interactive queries.
+    pub priority: QueryPriority,
 }
 
 impl Default for TraversalOptions {
@@ -98,6 +110,8 @@ impl Default for TraversalOptions {
             filter_query: None,
             do_uncompact: true,
             include_buffer: 0,
+            sort_strategy: TraversalSortStrategy::default(),
+            priority: QueryPriority::default(),
         }
     }
 }
@@ -119,6 +133,101 @@ impl TraversalOptions {
     }
 }
 
+/// The order in which the cells of the traversal are visited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraversalSortStrategy {
+    /// sorted by the h3index. Deterministic, but successive cells are not necessarily
+    /// spatially adjacent.
+    H3Index,
+
+    /// sorted along a Hilbert space-filling curve using the centroids of the cells.
+    ///
+    /// Successive cells are spatially close to each other, which improves the locality of the
+    /// queries and by that the cache hit rates of ClickHouse.
+    HilbertCurve,
+
+    /// a random order generated from the given seed.
+    ///
+    /// The same seed always results in the same order, so multiple workers using the same seed
+    /// can split the shuffled cells among each other without areas with dense data all ending
+    /// up with a single worker.
+    Shuffled(u64),
+}
+
+impl Default for TraversalSortStrategy {
+    fn default() -> Self {
+        Self::H3Index
+    }
+}
+
+impl TraversalSortStrategy {
+    /// sort the cells according to the strategy. The cells are expected to be de-duplicated.
+    pub fn sort_cells(&self, cells: &mut [H3Cell]) -> Result<(), Error> {
+        match self {
+            Self::H3Index => cells.sort_unstable(),
+            Self::HilbertCurve => {
+                let mut keyed_cells = cells
+                    .iter()
+                    .map(|cell| cell.to_coordinate().map(|c| (hilbert_key(c), *cell)))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                // including the cell in the comparison keeps the order deterministic for cells
+                // sharing the same hilbert key.
+                keyed_cells.sort_unstable();
+                for (cell, (_, keyed_cell)) in cells.iter_mut().zip(keyed_cells) {
+                    *cell = keyed_cell;
+                }
+            }
+            Self::Shuffled(seed) => {
+                // sorting first makes the order independent of the order of the input cells
+                cells.sort_unstable();
+                cells.shuffle(&mut StdRng::seed_from_u64(*seed));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// order of the hilbert curve used to sort traversal cells. 2^16 steps per axis are
+/// more than enough to separate cells of all resolutions used for traversal.
+const HILBERT_ORDER: u32 = 16;
+
+/// hilbert key of a WGS84 coordinate
+fn hilbert_key(coord: Coord<f64>) -> u64 {
+    let max_xy = ((1_u32 << HILBERT_ORDER) - 1) as f64;
+    let scale = |value: f64, min: f64, span: f64| {
+        (((value - min) / span) * max_xy).round().clamp(0.0, max_xy) as u32
+    };
+    hilbert_xy2d(
+        HILBERT_ORDER,
+        scale(coord.x, -180.0, 360.0),
+        scale(coord.y, -90.0, 180.0),
+    )
+}
+
+/// distance along the hilbert curve of the given `order` for the grid position `x`, `y`.
+fn hilbert_xy2d(order: u32, mut x: u32, mut y: u32) -> u64 {
+    let n = 1_u32 << order;
+    let mut d = 0_u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from((x & s) > 0);
+        let ry = u32::from((y & s) > 0);
+        d += (s as u64) * (s as u64) * (((3 * rx) ^ ry) as u64);
+
+        // rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
 pub enum TraversalArea {
     Geometry(Geometry<f64>),
     H3Cells(Vec<H3Cell>),
@@ -179,6 +288,34 @@ pub struct Traverser {
     pub traversal_h3_resolution: u8,
     dataframe_recv: tokio::sync::mpsc::Receiver<Result<TraversedCell, Error>>,
     num_cells_already_traversed: usize,
+
+    /// traversal cells skipped by the workers because they did not contain any data
+    num_cells_skipped: Arc<AtomicUsize>,
+    finished: bool,
+}
+
+impl Traverser {
+    /// Number of traversal cells which have been traversed so far. This includes the
+    /// cells which were skipped because they did not contain any data.
+    pub fn num_traversed_cells(&self) -> usize {
+        if self.finished {
+            self.num_traversal_cells
+        } else {
+            (self.num_cells_already_traversed + self.num_cells_skipped.load(Ordering::Relaxed))
+                .min(self.num_traversal_cells)
+        }
+    }
+
+    /// The fraction of the traversal cells traversed so far in the range `0.0..=1.0`.
+    ///
+    /// Traversals without any traversal cells are always complete.
+    pub fn fraction_complete(&self) -> f64 {
+        if self.num_traversal_cells == 0 {
+            1.0
+        } else {
+            self.num_traversed_cells() as f64 / self.num_traversal_cells as f64
+        }
+    }
 }
 
 impl Stream for Traverser {
@@ -188,8 +325,10 @@ impl Stream for Traverser {
         let self_mut = self.get_mut();
         let polled = self_mut.dataframe_recv.poll_recv(cx);
 
-        if polled.is_ready() {
-            self_mut.num_cells_already_traversed += 1;
+        match &polled {
+            Poll::Ready(Some(_)) => self_mut.num_cells_already_traversed += 1,
+            Poll::Ready(None) => self_mut.finished = true,
+            Poll::Pending => (),
         }
         polled
     }
@@ -199,26 +338,32 @@ impl Stream for Traverser {
         // We may yield less here when traversal cells to not contain data
         let num_cells_outstanding = self
             .num_traversal_cells
-            .saturating_sub(self.num_cells_already_traversed);
+            .saturating_sub(self.num_traversed_cells());
         (num_cells_outstanding, Some(num_cells_outstanding))
     }
 }
 
-pub async fn traverse(
-    client: &mut Client,
+pub async fn traverse<C>(
+    client: &mut C,
     database_name: String,
     tableset_name: String,
     area: &TraversalArea,
     options: TraversalOptions,
-) -> Result<Traverser, Error> {
+) -> Result<Traverser, Error>
+where
+    C: ArrowInterface + Clone + Send + Sync + 'static,
+{
     let tableset = client.get_tableset(&database_name, tableset_name).await?;
+    validate_traversal_h3_resolution(&tableset, options.h3_resolution)?;
+
     let traversal_h3_resolution = select_traversal_resolution(
         &tableset,
         options.h3_resolution,
         options.max_h3indexes_fetch_count,
     );
 
-    let traversal_cells = area.to_cells(traversal_h3_resolution)?;
+    let mut traversal_cells = area.to_cells(traversal_h3_resolution)?;
+    options.sort_strategy.sort_cells(&mut traversal_cells)?;
 
     traverse_inner(
         client,
@@ -231,14 +376,31 @@ pub async fn traverse(
     .await
 }
 
-async fn traverse_inner(
-    client: &mut Client,
+/// fail early instead of failing on each of the traversed cells when the tableset
+/// has no data at the requested resolution
+fn validate_traversal_h3_resolution(tableset: &TableSet, h3_resolution: u8) -> Result<(), Error> {
+    let base_resolutions = tableset.base_resolutions();
+    if base_resolutions.contains(&h3_resolution) {
+        Ok(())
+    } else {
+        Err(Error::H3ResolutionNotInTableSet(
+            h3_resolution,
+            base_resolutions,
+        ))
+    }
+}
+
+async fn traverse_inner<C>(
+    client: &mut C,
     database_name: String,
     tableset: TableSet,
     traversal_cells: Vec<H3Cell>,
     options: TraversalOptions,
     traversal_h3_resolution: u8,
-) -> Result<Traverser, Error> {
+) -> Result<Traverser, Error>
+where
+    C: ArrowInterface + Clone + Send + Sync + 'static,
+{
     let do_uncompact = options.do_uncompact;
     let num_traversal_cells = traversal_cells.len();
     let h3_resolution = options.h3_resolution;
@@ -247,8 +409,11 @@ async fn traverse_inner(
         client: client.clone(),
         database_name,
         tableset,
+        priority: options.priority,
     };
     let (dataframe_send, dataframe_recv) = tokio::sync::mpsc::channel(options.num_connections);
+    let num_cells_skipped = Arc::new(AtomicUsize::new(0));
+    let background_num_cells_skipped = num_cells_skipped.clone();
 
     let _background_fetch = spawn(async move {
         let (mut trav_cells_send, _trav_cells_recv) =
@@ -260,6 +425,7 @@ async fn traverse_inner(
             let mut worker_trav_cells_recv = trav_cells_send.subscribe();
             let worker_dataframe_send = dataframe_send.clone();
             let worker_query = options.query.clone();
+            let worker_num_cells_skipped = background_num_cells_skipped.clone();
 
             spawn(async move {
                 while let Some(cell) = worker_trav_cells_recv.recv().await {
@@ -277,6 +443,7 @@ async fn traverse_inner(
                         Ok(None) => {
                             // no data found, continue to the next cell
                             debug!("traversal cell yielded no data - skipping");
+                            worker_num_cells_skipped.fetch_add(1, Ordering::Relaxed);
                             continue;
                         }
                         Err(e) => Err(e),
@@ -296,17 +463,20 @@ async fn traverse_inner(
         let _ = spawn(async move {
             if let Some(filter_query) = &options.filter_query {
                 for cell_chunk in traversal_cells.chunks(50) {
-                    dispatch_traversal_cells(
-                        &mut trav_cells_send,
-                        prefilter_traversal_cells(
-                            &mut context,
-                            filter_query.clone(),
-                            cell_chunk,
-                            traversal_h3_resolution,
-                        )
-                        .await,
+                    let filtered_cells = prefilter_traversal_cells(
+                        &mut context,
+                        filter_query.clone(),
+                        cell_chunk,
+                        traversal_h3_resolution,
                     )
                     .await;
+                    if let Ok(cells) = &filtered_cells {
+                        background_num_cells_skipped.fetch_add(
+                            cell_chunk.len().saturating_sub(cells.len()),
+                            Ordering::Relaxed,
+                        );
+                    }
+                    dispatch_traversal_cells(&mut trav_cells_send, filtered_cells).await;
                 }
             } else {
                 dispatch_traversal_cells(&mut trav_cells_send, Ok(traversal_cells)).await;
@@ -323,14 +493,17 @@ async fn traverse_inner(
         traversal_h3_resolution,
         dataframe_recv,
         num_cells_already_traversed: 0,
+        num_cells_skipped,
+        finished: false,
     })
 }
 
 #[derive(Clone)]
-struct WorkerContext {
-    client: Client,
+struct WorkerContext<C> {
+    client: C,
     database_name: String,
     tableset: TableSet,
+    priority: QueryPriority,
 }
 
 async fn dispatch_traversal_cells(
@@ -354,22 +527,28 @@ async fn dispatch_traversal_cells(
     }
 }
 
-async fn prefilter_traversal_cells(
-    worker_context: &mut WorkerContext,
+async fn prefilter_traversal_cells<C>(
+    worker_context: &mut WorkerContext<C>,
     filter_query: TableSetQuery,
     cells: &[H3Cell],
     traversal_h3_resolution: u8,
-) -> Result<Vec<H3Cell>, Error> {
+) -> Result<Vec<H3Cell>, Error>
+where
+    C: ArrowInterface + Clone + Send + Sync,
+{
     if cells.is_empty() {
         return Ok(vec![]);
     }
 
+    let mut query_options =
+        QueryOptions::new(filter_query, cells.to_vec(), traversal_h3_resolution);
+    query_options.priority = worker_context.priority;
     let filter_h3df = worker_context
         .client
         .query_tableset_cells(
             &worker_context.database_name,
             worker_context.tableset.clone(),
-            QueryOptions::new(filter_query, cells.to_vec(), traversal_h3_resolution),
+            query_options,
         )
         .await?;
 
@@ -417,14 +596,17 @@ fn buffer_cell(
     Ok(cellset.drain().collect())
 }
 
-async fn load_traversed_cell(
-    worker_context: &mut WorkerContext,
+async fn load_traversed_cell<C>(
+    worker_context: &mut WorkerContext<C>,
     query: TableSetQuery,
     cell: Result<H3Cell, Error>,
     h3_resolution: u8,
     do_uncompact: bool,
     include_buffer: u32,
-) -> Result<Option<TraversedCell>, Error> {
+) -> Result<Option<TraversedCell>, Error>
+where
+    C: ArrowInterface + Clone + Send + Sync,
+{
     match cell {
         Ok(cell) => {
             let cells_to_load = if include_buffer == 0 {
@@ -435,6 +617,7 @@ async fn load_traversed_cell(
 
             let mut query_options = QueryOptions::new(query, cells_to_load, h3_resolution);
             query_options.do_uncompact = do_uncompact;
+            query_options.priority = worker_context.priority;
 
             let contained_data = worker_context
                 .client
@@ -462,3 +645,201 @@ async fn load_traversed_cell(
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use futures::{Stream, StreamExt};
+    use geo_types::{Coord, Geometry, MultiPolygon, Rect};
+    use h3ron::{H3Cell, Index};
+
+    use crate::clickhouse::compacted_tables::tableset::find_tablesets;
+    use crate::Error;
+
+    use super::{
+        buffer_cell, hilbert_xy2d, validate_traversal_h3_resolution, TraversalArea,
+        TraversalSortStrategy, TraversedCell, Traverser,
+    };
+
+    #[test]
+    fn hilbert_xy2d_order_1() {
+        assert_eq!(hilbert_xy2d(1, 0, 0), 0);
+        assert_eq!(hilbert_xy2d(1, 0, 1), 1);
+        assert_eq!(hilbert_xy2d(1, 1, 1), 2);
+        assert_eq!(hilbert_xy2d(1, 1, 0), 3);
+    }
+
+    #[test]
+    fn hilbert_xy2d_successive_are_adjacent() {
+        let order = 3;
+        let n = 1_u32 << order;
+        let mut positions = vec![(0_u64, 0_u32, 0_u32); (n * n) as usize];
+        for x in 0..n {
+            for y in 0..n {
+                let d = hilbert_xy2d(order, x, y);
+                positions[d as usize] = (d, x, y);
+            }
+        }
+        for window in positions.windows(2) {
+            let (_, x0, y0) = window[0];
+            let (_, x1, y1) = window[1];
+            assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1);
+        }
+    }
+
+    /// sort a disk of cells using the `strategy` and check that the result is a reordering
+    /// of the cells. Returns the cells sorted by their index and by the `strategy`.
+    fn sort_cells_permutation(strategy: TraversalSortStrategy) -> (Vec<H3Cell>, Vec<H3Cell>) {
+        let cell = H3Cell::new(0x89283080ddbffff_u64);
+        let mut sorted_by_index: Vec<_> = cell.grid_disk(5).unwrap().iter().collect();
+        sorted_by_index.sort_unstable();
+
+        let mut cells = sorted_by_index.clone();
+        strategy.sort_cells(&mut cells).unwrap();
+        assert_ne!(cells, sorted_by_index);
+
+        let mut resorted = cells.clone();
+        resorted.sort_unstable();
+        assert_eq!(resorted, sorted_by_index);
+        (sorted_by_index, cells)
+    }
+
+    #[test]
+    fn sort_cells_hilbert_curve() {
+        sort_cells_permutation(TraversalSortStrategy::HilbertCurve);
+    }
+
+    #[test]
+    fn traversal_area_multipolygon() {
+        let parts = [
+            Rect::new(Coord::from((22.5, -19.5)), Coord::from((23.0, -19.0))).to_polygon(),
+            Rect::new(Coord::from((10.0, 40.0)), Coord::from((10.5, 40.5))).to_polygon(),
+            // overlaps with the first part
+            Rect::new(Coord::from((22.8, -19.3)), Coord::from((23.2, -18.9))).to_polygon(),
+        ];
+        let mut expected: Vec<_> = parts
+            .iter()
+            .flat_map(|part| {
+                TraversalArea::Geometry(Geometry::Polygon(part.clone()))
+                    .to_cells(5)
+                    .unwrap()
+            })
+            .collect();
+        expected.sort_unstable();
+        expected.dedup();
+
+        let cells = TraversalArea::from(Geometry::MultiPolygon(MultiPolygon::new(parts.to_vec())))
+            .to_cells(5)
+            .unwrap();
+        assert_eq!(cells, expected);
+        assert!(cells.iter().all(|cell| cell.resolution() == 5));
+
+        // parts too small to contain the center of any cell still lead to cells
+        let tiny =
+            Rect::new(Coord::from((22.80, -19.30)), Coord::from((22.81, -19.29))).to_polygon();
+        let cells = TraversalArea::from(Geometry::MultiPolygon(MultiPolygon::new(vec![tiny])))
+            .to_cells(5)
+            .unwrap();
+        assert!(!cells.is_empty());
+    }
+
+    #[test]
+    fn sort_cells_shuffled() {
+        let (sorted_by_index, cells) = sort_cells_permutation(TraversalSortStrategy::Shuffled(7));
+
+        // the order only depends on the seed, not on the order of the input cells
+        let mut reversed: Vec<_> = sorted_by_index.iter().rev().copied().collect();
+        TraversalSortStrategy::Shuffled(7)
+            .sort_cells(&mut reversed)
+            .unwrap();
+        assert_eq!(reversed, cells);
+
+        let mut other_seed = sorted_by_index;
+        TraversalSortStrategy::Shuffled(8)
+            .sort_cells(&mut other_seed)
+            .unwrap();
+        assert_ne!(other_seed, cells);
+    }
+
+    #[test]
+    fn buffer_cell_width() {
+        let cell = H3Cell::from_coordinate(Coord::from((22.8, -19.3)), 4).unwrap();
+        let children = cell.get_children(6).unwrap();
+
+        let unbuffered = buffer_cell(cell, 6, 0).unwrap();
+        assert_eq!(unbuffered.len(), children.iter().count());
+
+        let buffered = buffer_cell(cell, 6, 2).unwrap();
+        assert!(buffered.len() > unbuffered.len());
+        assert!(unbuffered.iter().all(|c| buffered.contains(c)));
+    }
+
+    #[test]
+    fn validate_traversal_resolution() {
+        let tableset = find_tablesets([
+            "elephants_04_compacted",
+            "elephants_04_base",
+            "elephants_05_base",
+        ])
+        .remove("elephants")
+        .unwrap();
+
+        assert!(validate_traversal_h3_resolution(&tableset, 5).is_ok());
+        assert!(validate_traversal_h3_resolution(&tableset, 4).is_ok());
+        match validate_traversal_h3_resolution(&tableset, 7) {
+            Err(Error::H3ResolutionNotInTableSet(7, available)) => {
+                assert_eq!(available, vec![4, 5])
+            }
+            _ => panic!("expected an error"),
+        }
+    }
+
+    fn make_traverser(
+        num_traversal_cells: usize,
+    ) -> (
+        Traverser,
+        tokio::sync::mpsc::Sender<Result<TraversedCell, Error>>,
+    ) {
+        let (sender, dataframe_recv) = tokio::sync::mpsc::channel(10);
+        let traverser = Traverser {
+            num_traversal_cells,
+            traversal_h3_resolution: 5,
+            dataframe_recv,
+            num_cells_already_traversed: 0,
+            num_cells_skipped: Default::default(),
+            finished: false,
+        };
+        (traverser, sender)
+    }
+
+    #[tokio::test]
+    async fn traverser_progress_empty() {
+        let (mut traverser, sender) = make_traverser(0);
+        assert_eq!(traverser.fraction_complete(), 1.0);
+        drop(sender);
+        assert!(traverser.next().await.is_none());
+        assert_eq!(traverser.num_traversed_cells(), 0);
+        assert_eq!(traverser.fraction_complete(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn traverser_progress() {
+        let (mut traverser, sender) = make_traverser(4);
+        assert_eq!(traverser.num_traversed_cells(), 0);
+        assert_eq!(traverser.fraction_complete(), 0.0);
+
+        sender.send(Err(Error::EmptyCells)).await.unwrap();
+        assert!(traverser.next().await.is_some());
+        traverser.num_cells_skipped.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(traverser.num_traversed_cells(), 2);
+        assert_eq!(traverser.fraction_complete(), 0.5);
+        assert_eq!(traverser.size_hint(), (2, Some(2)));
+
+        // the remaining cells did not contain data, the traversal is complete once the stream ends
+        drop(sender);
+        assert!(traverser.next().await.is_none());
+        assert_eq!(traverser.num_traversed_cells(), 4);
+        assert_eq!(traverser.fraction_complete(), 1.0);
+    }
+}
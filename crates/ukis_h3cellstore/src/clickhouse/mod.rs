@@ -2,5 +2,6 @@ pub use cellstore::H3CellStore;
 
 pub mod cellstore;
 pub mod compacted_tables;
+pub mod retry;
 #[cfg(feature = "sync")]
 pub mod sync;
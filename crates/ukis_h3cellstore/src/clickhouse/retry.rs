@@ -0,0 +1,197 @@
+//!
+//! Retrying of operations failing with transient errors, like overloaded servers or
+//! network issues.
+//!
+use std::future::Future;
+use std::time::Duration;
+
+use rand::{thread_rng, Rng};
+use tracing::warn;
+
+use crate::Error;
+
+/// Policy how often and in which intervals failed operations are retried.
+///
+/// Only errors for which [`Error::is_transient`] returns `true` are retried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// the maximum number of attempts including the first one. `1` disables retrying.
+    pub max_attempts: u8,
+
+    /// backoff before the first retry
+    pub initial_backoff_ms: u64,
+
+    /// factor to grow the backoff with after each retry
+    pub backoff_multiplier: f64,
+
+    /// upper limit of the backoff between two attempts
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 200,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: 5_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// a policy which does not retry at all
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// the backoff before the given retry, without the jitter
+    fn backoff_ms(&self, retry: u8) -> u64 {
+        let backoff = self.initial_backoff_ms as f64
+            * self
+                .backoff_multiplier
+                .max(1.0)
+                .powi(retry.saturating_sub(1) as i32);
+        backoff.min(self.max_backoff_ms as f64) as u64
+    }
+
+    /// the backoff with a random jitter of up to half of its length to avoid
+    /// multiple clients retrying in lockstep.
+    fn backoff_with_jitter(&self, retry: u8) -> Duration {
+        let backoff_ms = self.backoff_ms(retry);
+        let jitter_ms = if backoff_ms > 1 {
+            thread_rng().gen_range(0..=(backoff_ms / 2))
+        } else {
+            0
+        };
+        Duration::from_millis(backoff_ms - jitter_ms)
+    }
+}
+
+/// run the operation created by `f`, retrying it according to the `policy` when it fails
+/// with a transient error.
+///
+/// The error of the last attempt is returned when all attempts failed.
+pub async fn with_retry<F, Fut, T>(
+    policy: &RetryPolicy,
+    operation_name: &str,
+    mut f: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < max_attempts && e.is_transient() => {
+                let backoff = policy.backoff_with_jitter(attempt);
+                warn!(
+                    "{} failed in attempt {} of {}: {}. retrying in {}ms",
+                    operation_name,
+                    attempt,
+                    max_attempts,
+                    e,
+                    backoff.as_millis()
+                );
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::clickhouse::retry::{with_retry, RetryPolicy};
+    use crate::error::tests::exception;
+    use crate::Error;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff_ms: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backoff_grows_until_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            backoff_multiplier: 2.0,
+            max_backoff_ms: 500,
+        };
+        assert_eq!(policy.backoff_ms(1), 100);
+        assert_eq!(policy.backoff_ms(2), 200);
+        assert_eq!(policy.backoff_ms(3), 400);
+        assert_eq!(policy.backoff_ms(4), 500);
+
+        for retry in 1..5 {
+            let backoff = policy.backoff_with_jitter(retry).as_millis() as u64;
+            assert!(backoff <= policy.backoff_ms(retry));
+            assert!(backoff >= policy.backoff_ms(retry) / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let num_calls = &AtomicUsize::new(0);
+        let result = with_retry(&fast_policy(), "test", || async move {
+            if num_calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(exception(
+                    "Code: 202. DB::Exception: Too many simultaneous queries",
+                ))
+            } else {
+                Ok(42)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(num_calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_after_max_attempts() {
+        let num_calls = &AtomicUsize::new(0);
+        let result: Result<(), _> = with_retry(&fast_policy(), "test", || async move {
+            num_calls.fetch_add(1, Ordering::SeqCst);
+            Err(exception("Code: 209. DB::NetException: Timeout exceeded"))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::ClickhouseException(_))));
+        assert_eq!(num_calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let num_calls = &AtomicUsize::new(0);
+        let result: Result<(), _> = with_retry(&fast_policy(), "test", || async move {
+            num_calls.fetch_add(1, Ordering::SeqCst);
+            Err(exception(
+                "Code: 60. DB::Exception: Table db.elephants_05_base doesn't exist",
+            ))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(num_calls.load(Ordering::SeqCst), 1);
+
+        let num_calls = &AtomicUsize::new(0);
+        let result: Result<(), _> = with_retry(&RetryPolicy::no_retry(), "test", || async move {
+            num_calls.fetch_add(1, Ordering::SeqCst);
+            Err(exception(
+                "Code: 202. DB::Exception: Too many simultaneous queries",
+            ))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(num_calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,5 +1,8 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
 use thiserror::Error as ThisError;
 
+use ukis_clickhouse_arrow_grpc::export::tonic::Code;
 use ukis_clickhouse_arrow_grpc::{ClickhouseException, Error as CAGError};
 
 #[derive(ThisError, Debug)]
@@ -19,8 +22,8 @@ pub enum Error {
     #[error(transparent)]
     TonicTansport(#[from] ukis_clickhouse_arrow_grpc::export::tonic::transport::Error),
 
-    #[error("ClickhouseException({})", .0.to_string())]
-    ClickhouseException(ClickhouseException),
+    #[error("clickhouse exception")]
+    ClickhouseException(#[source] ClickhouseException),
 
     #[error("mismatch of arrays in chunk to number of casts")]
     CastArrayLengthMismatch,
@@ -43,6 +46,15 @@ pub enum Error {
     #[error("Unsupported H3 resolution: {0}")]
     UnsupportedH3Resolution(u8),
 
+    #[error("h3 resolution {0} is not available in the tableset. Available resolutions: {1:?}")]
+    H3ResolutionNotInTableSet(u8, Vec<u8>),
+
+    #[error("unknown datatype: {0}")]
+    UnknownDatatype(String),
+
+    #[error("incompatible datatype of column {0}: {1} can not be changed to {2}")]
+    IncompatibleDatatype(String, String, String),
+
     #[error("no queryable tables found")]
     NoQueryableTables,
 
@@ -55,6 +67,9 @@ pub enum Error {
     #[error("missing query placeholder {0}")]
     MissingQueryPlaceholder(String),
 
+    #[error("invalid query limit: {0}")]
+    InvalidQueryLimit(u64),
+
     #[error("schema error validating {0}: {1}")]
     SchemaValidationError(&'static str, String),
 
@@ -67,12 +82,18 @@ pub enum Error {
     #[error("tableset not found: {0}")]
     TableSetNotFound(String),
 
+    #[error("tableset already exists: {0}")]
+    TableSetAlreadyExists(String),
+
     #[error("database not found: {0}")]
     DatabaseNotFound(String),
 
     #[error("missing index value")]
     MissingIndexValue,
 
+    #[error("invalid h3index: {0}")]
+    InvalidH3Index(u64),
+
     #[error("abort has been triggered")]
     Abort,
 
@@ -83,6 +104,42 @@ pub enum Error {
     Io(#[from] std::io::Error),
 }
 
+/// ClickHouse error codes of failures which may succeed when being retried
+///
+/// * 159: TIMEOUT_EXCEEDED
+/// * 202: TOO_MANY_SIMULTANEOUS_QUERIES
+/// * 209: SOCKET_TIMEOUT
+/// * 210: NETWORK_ERROR
+/// * 252: TOO_MANY_PARTS
+/// * 999: KEEPER_EXCEPTION
+const TRANSIENT_CLICKHOUSE_ERROR_CODES: &[u32] = &[159, 202, 209, 210, 252, 999];
+
+static RE_CLICKHOUSE_ERROR_CODE: Lazy<Regex> = Lazy::new(|| Regex::new(r"Code: ([0-9]+)").unwrap());
+
+impl Error {
+    /// check if the error is caused by a temporary condition like an overloaded server or a
+    /// network failure, so retrying the failed operation may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::TonicStatus(status) => matches!(
+                status.code(),
+                Code::Unavailable
+                    | Code::ResourceExhausted
+                    | Code::Aborted
+                    | Code::DeadlineExceeded
+            ),
+            Self::TonicTansport(_) => true,
+            Self::ClickhouseException(ce) => RE_CLICKHOUSE_ERROR_CODE
+                .captures(&ce.display_text)
+                .and_then(|caps| caps.get(1))
+                .and_then(|code| code.as_str().parse::<u32>().ok())
+                .map(|code| TRANSIENT_CLICKHOUSE_ERROR_CODES.contains(&code))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
 impl From<CAGError> for Error {
     fn from(cagerror: CAGError) -> Self {
         match cagerror {
@@ -96,3 +153,56 @@ impl From<CAGError> for Error {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::error::Error as StdError;
+
+    use ukis_clickhouse_arrow_grpc::export::tonic::Status;
+    use ukis_clickhouse_arrow_grpc::{ClickhouseException, Error as CAGError};
+
+    use crate::Error;
+
+    /// a `ClickhouseException` error with the given text
+    pub(crate) fn exception(display_text: &str) -> Error {
+        Error::ClickhouseException(ClickhouseException {
+            name: "DB::Exception".to_string(),
+            display_text: display_text.to_string(),
+            stack_trace: "".to_string(),
+        })
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(
+            exception("Code: 202. DB::Exception: Too many simultaneous queries").is_transient()
+        );
+        assert!(exception("Code: 252. DB::Exception: Too many parts (300)").is_transient());
+        assert!(!exception("Code: 60. DB::Exception: Table db.t doesn't exist").is_transient());
+        assert!(!exception("Table db.t doesn't exist").is_transient());
+
+        assert!(Error::TonicStatus(Status::unavailable("connection reset")).is_transient());
+        assert!(!Error::TonicStatus(Status::invalid_argument("invalid query")).is_transient());
+        assert!(!Error::EmptyCells.is_transient());
+    }
+
+    #[test]
+    fn clickhouse_exception_source() {
+        let err: Error = CAGError::ClickhouseException(ClickhouseException {
+            name: "DB::Exception".to_string(),
+            display_text: "Table db.elephants_05_base doesn't exist".to_string(),
+            stack_trace: "".to_string(),
+        })
+        .into();
+
+        // the message of the exception is only part of the source, not repeated by the error
+        assert_eq!(err.to_string(), "clickhouse exception");
+        let source = err.source().expect("exception as source");
+        assert!(source.is::<ClickhouseException>());
+        assert_eq!(
+            source.to_string(),
+            "DB::Exception: Table db.elephants_05_base doesn't exist"
+        );
+        assert!(source.source().is_none());
+    }
+}
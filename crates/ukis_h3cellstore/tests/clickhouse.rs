@@ -0,0 +1,187 @@
+//! Integration tests requiring a running ClickHouse server.
+//!
+//! The tests are only built with the `integration-tests` feature enabled. The gRPC endpoint of the
+//! server is read from the `CLICKHOUSE_GRPC_TESTING_ENDPOINT` environment variable and defaults
+//! to the server started by `just clickhouse`.
+#![cfg(feature = "integration-tests")]
+
+use chrono::Local;
+use geo_types::Coord;
+use h3ron::H3Cell;
+use h3ron_polars::frame::H3DataFrame;
+use h3ron_polars::FromIndexIterator;
+use polars::prelude::{DataFrame, NamedFrom, Series};
+
+use ukis_h3cellstore::clickhouse::compacted_tables::schema::{
+    AggregationMethod, ClickhouseDataType, ColumnDefinition, CompactedTableSchema,
+    CompactedTableSchemaBuilder, SimpleColumn, TemporalPartitioning,
+};
+use ukis_h3cellstore::clickhouse::compacted_tables::{
+    CompactedTablesStore, InsertOptions, QueryOptions, COL_NAME_H3INDEX,
+};
+use ukis_h3cellstore::export::ukis_clickhouse_arrow_grpc::{ArrowInterface, Client, QueryInfo};
+
+const MAX_H3_RES: u8 = 5;
+
+fn clickhouse_grpc_endpoint() -> String {
+    std::env::var("CLICKHOUSE_GRPC_TESTING_ENDPOINT")
+        .unwrap_or_else(|_| "http://127.0.0.1:9100".to_string())
+}
+
+/// connect to the server and (re-)create an empty database for the test
+async fn connect_to_empty_database(database_name: &str) -> Client {
+    let mut client = Client::connect(clickhouse_grpc_endpoint())
+        .await
+        .expect("connecting to clickhouse failed");
+    for query in [
+        format!("drop database if exists {}", database_name),
+        format!("create database {}", database_name),
+    ] {
+        client
+            .execute_query_checked(QueryInfo {
+                query,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    client
+}
+
+fn okavango_delta_schema() -> CompactedTableSchema {
+    CompactedTableSchemaBuilder::new("okavango_delta")
+        .h3_resolution_range(0, MAX_H3_RES)
+        .temporal_partitioning(TemporalPartitioning::Months(1))
+        .add_column(
+            "elephant_count",
+            ColumnDefinition::WithAggregation(
+                SimpleColumn::new(ClickhouseDataType::UInt32, None, None, false),
+                AggregationMethod::Sum,
+            ),
+        )
+        .add_column(
+            "observed_on",
+            ColumnDefinition::Simple(SimpleColumn::new(
+                ClickhouseDataType::DateTime64,
+                Some(0),
+                None,
+                false,
+            )),
+        )
+        .build()
+        .unwrap()
+}
+
+fn okavango_delta_h3dataframe(center: Coord<f64>) -> H3DataFrame<H3Cell> {
+    let mut index_series = Series::from_index_iter(
+        H3Cell::from_coordinate(center, MAX_H3_RES)
+            .unwrap()
+            .grid_disk(10)
+            .unwrap()
+            .iter(),
+    );
+    index_series.rename(COL_NAME_H3INDEX);
+
+    let num_cells = index_series.len();
+    let df = DataFrame::new(vec![
+        index_series,
+        Series::new(
+            "elephant_count",
+            (0..num_cells).map(|_| 2_u32).collect::<Vec<_>>(),
+        ),
+        Series::new(
+            "observed_on",
+            (0..num_cells)
+                .map(|_| Local::now().naive_local())
+                .collect::<Vec<_>>(),
+        ),
+    ])
+    .unwrap();
+    H3DataFrame::from_dataframe(df, COL_NAME_H3INDEX).unwrap()
+}
+
+#[tokio::test]
+async fn create_list_and_drop_tableset() {
+    let db = "h3cellstore_it_create_list_drop";
+    let mut client = connect_to_empty_database(db).await;
+    let schema = okavango_delta_schema();
+
+    client.create_tableset(db, &schema).await.unwrap();
+    let tableset = client.get_tableset(db, &schema.name).await.unwrap();
+    assert_eq!(
+        tableset.base_resolutions(),
+        (0..=MAX_H3_RES).collect::<Vec<_>>()
+    );
+    assert!(tableset.columns.contains_key("elephant_count"));
+
+    assert_eq!(
+        client.drop_tableset(db, &schema.name).await.unwrap(),
+        2 * (MAX_H3_RES as usize + 1)
+    );
+    assert_eq!(client.drop_tableset(db, &schema.name).await.unwrap(), 0);
+    assert!(!client
+        .list_tablesets(db)
+        .await
+        .unwrap()
+        .contains_key(&schema.name));
+}
+
+#[tokio::test]
+async fn insert_and_query_roundtrip() {
+    let db = "h3cellstore_it_insert_query";
+    let mut client = connect_to_empty_database(db).await;
+    let schema = okavango_delta_schema();
+    let center = Coord::from((22.8996, -19.3325));
+
+    client.create_tableset(db, &schema).await.unwrap();
+    client
+        .insert_h3dataframe_into_tableset(
+            db,
+            &schema,
+            okavango_delta_h3dataframe(center),
+            InsertOptions {
+                max_num_rows_per_chunk: 20,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let queried = client
+        .query_tableset_cells(
+            db,
+            &schema.name,
+            QueryOptions::new(
+                Default::default(),
+                vec![H3Cell::from_coordinate(center, MAX_H3_RES - 1).unwrap()],
+                MAX_H3_RES,
+            ),
+        )
+        .await
+        .unwrap();
+    assert_eq!(queried.dataframe().shape().0, 7);
+
+    let stats = client.tableset_stats(db, &schema.name).await.unwrap();
+    assert_eq!(stats.shape().0, (MAX_H3_RES + 1) as usize);
+}
+
+#[tokio::test]
+async fn reconstruct_schema() {
+    let db = "h3cellstore_it_reconstruct_schema";
+    let mut client = connect_to_empty_database(db).await;
+    let schema = okavango_delta_schema();
+    client.create_tableset(db, &schema).await.unwrap();
+
+    let reconstructed = CompactedTableSchema::from_clickhouse(&mut client, db, &schema.name)
+        .await
+        .unwrap();
+    assert_eq!(reconstructed.name, schema.name);
+    assert_eq!(
+        reconstructed.max_h3_resolution().unwrap(),
+        schema.max_h3_resolution().unwrap()
+    );
+    assert_eq!(
+        reconstructed.build_create_statements(&None).unwrap().len(),
+        schema.build_create_statements(&None).unwrap().len()
+    );
+}
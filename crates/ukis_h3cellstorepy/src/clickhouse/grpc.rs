@@ -5,7 +5,7 @@ use crate::error::IntoPyResult;
 use crate::frame::{dataframe_from_pyany, ToDataframeWrapper};
 use crate::utils::indexes_from_numpy;
 use numpy::PyReadonlyArray1;
-use pyo3::exceptions::{PyIOError, PyRuntimeError};
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use pyo3::PyResult;
@@ -17,12 +17,12 @@ use tokio::sync::oneshot::error::TryRecvError;
 use tracing::debug_span;
 use tracing::warn;
 use ukis_h3cellstore::clickhouse::compacted_tables::{
-    CompactedTablesStore, InsertOptions, QueryOptions, TableSetQuery,
+    CompactedTablesStore, InsertObserver, InsertOptions, QueryOptions, TableSetQuery,
 };
 use ukis_h3cellstore::clickhouse::H3CellStore;
 use ukis_h3cellstore::export::h3ron_polars::frame::H3DataFrame;
 use ukis_h3cellstore::export::ukis_clickhouse_arrow_grpc::{
-    ArrowInterface, Client, QueryInfo, DEFAULT_MAX_MESSAGE_SIZE,
+    ArrowInterface, Client, ClientConfig, QueryInfo, DEFAULT_MAX_MESSAGE_SIZE,
 };
 
 #[derive(Clone)]
@@ -91,14 +91,19 @@ impl GRPCConnection {
     }
 
     /// Establish a new connection
+    ///
+    /// The optional `connect_timeout_ms` limits the time to establish the connection,
+    /// `request_timeout_ms` the time each query may take.
     #[new]
-    #[pyo3(signature = (grpc_endpoint, database_name, create_db = false, runtime = None, max_message_size = None))]
+    #[pyo3(signature = (grpc_endpoint, database_name, create_db = false, runtime = None, max_message_size = None, connect_timeout_ms = None, request_timeout_ms = None))]
     pub fn new(
         grpc_endpoint: &str,
         database_name: &str,
         create_db: bool,
         runtime: Option<GRPCRuntime>,
         max_message_size: Option<usize>,
+        connect_timeout_ms: Option<u64>,
+        request_timeout_ms: Option<u64>,
     ) -> PyResult<Self> {
         let runtime = match runtime {
             None => obtain_runtime()?,
@@ -106,9 +111,14 @@ impl GRPCConnection {
         };
         let grpc_endpoint_str = grpc_endpoint.to_string();
         let db_name_str = database_name.to_string();
-        let client = runtime.block_on(async {
-            connect(grpc_endpoint_str, db_name_str, create_db, max_message_size).await
-        })?;
+        let config = ClientConfig {
+            max_message_size: max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            connect_timeout: connect_timeout_ms.map(Duration::from_millis),
+            request_timeout: request_timeout_ms.map(Duration::from_millis),
+            ..Default::default()
+        };
+        let client = runtime
+            .block_on(async { connect(grpc_endpoint_str, db_name_str, create_db, config).await })?;
 
         Ok(Self {
             database_name: database_name.to_string(),
@@ -117,8 +127,13 @@ impl GRPCConnection {
         })
     }
 
-    /// execute the given query in the database without returning any result
+    /// execute the given query in the database without returning any result.
+    ///
+    /// Useful to run DDL statements like `OPTIMIZE TABLE ...` or `ALTER TABLE ...`.
     pub fn execute(&mut self, query: String) -> PyResult<()> {
+        if query.trim().is_empty() {
+            return Err(PyValueError::new_err("query must not be empty"));
+        }
         self.runtime
             .block_on(async {
                 self.client
@@ -202,8 +217,8 @@ impl GRPCConnection {
             .collect())
     }
 
-    /// drop the tableset with the given name
-    pub fn drop_tableset(&mut self, tableset_name: String) -> PyResult<()> {
+    /// drop the tableset with the given name. Returns the number of dropped tables.
+    pub fn drop_tableset(&mut self, tableset_name: String) -> PyResult<usize> {
         self.runtime
             .block_on(async {
                 self.client
@@ -213,6 +228,39 @@ impl GRPCConnection {
             .into_pyresult()
     }
 
+    /// copy the tableset described by `schema` including its data to a new tableset named
+    /// `target_tableset_name`. The copy is not atomic, the tables created so far are dropped
+    /// again when it fails.
+    pub fn copy_tableset(
+        &mut self,
+        schema: &PyCompactedTableSchema,
+        target_tableset_name: String,
+    ) -> PyResult<()> {
+        self.runtime
+            .block_on(async {
+                self.client
+                    .copy_tableset(&self.database_name, &schema.schema, target_tableset_name)
+                    .await
+            })
+            .into_pyresult()
+    }
+
+    /// move the tableset described by `schema` including its data to a new tableset named
+    /// `target_tableset_name`
+    pub fn move_tableset(
+        &mut self,
+        schema: &PyCompactedTableSchema,
+        target_tableset_name: String,
+    ) -> PyResult<()> {
+        self.runtime
+            .block_on(async {
+                self.client
+                    .move_tableset(&self.database_name, &schema.schema, target_tableset_name)
+                    .await
+            })
+            .into_pyresult()
+    }
+
     /// create the schema based on the schema definition in the database
     pub fn create_tableset(&mut self, schema: &PyCompactedTableSchema) -> PyResult<()> {
         self.runtime
@@ -279,13 +327,21 @@ impl GRPCConnection {
                     self.runtime.block_on(joinhandle).into_pyresult()?;
                     return res;
                 }
-                Err(TryRecvError::Empty) => std::thread::sleep(Duration::from_millis(100)),
+                // release the GIL while waiting to allow progress callbacks to acquire it
+                Err(TryRecvError::Empty) => Python::with_gil(|py| {
+                    py.allow_threads(|| std::thread::sleep(Duration::from_millis(100)))
+                }),
                 Err(TryRecvError::Closed) => unreachable!(),
             }
         }
     }
 
-    #[pyo3(signature = (tableset_name, query, cells, h3_resolution, do_uncompact = true))]
+    /// Query the cells of a tableset.
+    ///
+    /// Setting `final_modifier` queries the tables using `FINAL` to get de-duplicated results before ClickHouse
+    /// merged the table parts in the background. This is significantly more expensive for large tables. Templated
+    /// queries must contain the `<[final]>` placeholder to be used with this option.
+    #[pyo3(signature = (tableset_name, query, cells, h3_resolution, do_uncompact = true, final_modifier = false))]
     pub fn query_tableset_cells(
         &mut self,
         tableset_name: String,
@@ -293,6 +349,7 @@ impl GRPCConnection {
         cells: PyReadonlyArray1<u64>,
         h3_resolution: u8,
         do_uncompact: bool,
+        final_modifier: bool,
     ) -> PyResult<PyObject> {
         let mut query_options = QueryOptions::new(
             query.query.clone(),
@@ -300,6 +357,7 @@ impl GRPCConnection {
             h3_resolution,
         );
         query_options.do_uncompact = do_uncompact;
+        query_options.final_modifier = final_modifier;
         self.runtime
             .block_on(async {
                 self.client
@@ -323,6 +381,9 @@ impl GRPCConnection {
     /// - `max_fetch_count`: The maximum number of cells to fetch in one DB query.
     /// - `num_connections`: Number of parallel DB connections to use in the background. Default is 3. Depending with the number of connections used the amount of memory used increases as well as the load put onto the DB-Server. The benefit is getting data faster as it is pre-loaded in the background.
     /// - `filter_query`: This query will be applied to the tables in the reduced `traversal_h3_resolution` and only cells found by this query will be loaded from the tables in the requested full resolution
+    /// - `sort_strategy`: The order in which the traversal cells are visited. One of `"h3index"` (default), `"hilbert"` or `"shuffled"`. `"hilbert"` traverses spatially adjacent cells after each other which improves the cache locality of successive queries. `"shuffled"` visits the cells in a random order determined by the `shuffle_seed` option (default: 0).
+    /// - `include_buffer`: Number of cells in `h3_resolution` by which the area of each traversal cell gets expanded. The data of the neighbouring cells is included in the returned dataframes. Default is 0.
+    /// - `priority`: Priority of the queries on the ClickHouse server. One of `"high"`, `"normal"` (default) or `"low"`. Low-priority traversals yield to other queries running with a higher priority.
     #[pyo3(signature = (tableset_name, query, area_of_interest, h3_resolution, **kwargs))]
     pub fn traverse_tableset_area_of_interest(
         &mut self,
@@ -355,20 +416,47 @@ impl GRPCConnection {
             .into_pyresult()?
             .to_dataframewrapper()
     }
+
+    /// fraction of all possible cells of the given resolution which are stored in the
+    /// base table of that resolution
+    pub fn resolution_coverage_fraction(
+        &mut self,
+        tableset_name: String,
+        h3_resolution: u8,
+    ) -> PyResult<f64> {
+        self.runtime
+            .block_on(async {
+                self.client
+                    .resolution_coverage_fraction(&self.database_name, tableset_name, h3_resolution)
+                    .await
+            })
+            .into_pyresult()
+    }
+
+    /// the number of rows stored in each table of the tableset, keyed by the table name
+    pub fn tableset_row_counts(&mut self, tableset_name: String) -> PyResult<HashMap<String, u64>> {
+        Ok(self
+            .runtime
+            .block_on(async {
+                self.client
+                    .tableset_row_counts(&self.database_name, tableset_name)
+                    .await
+            })
+            .into_pyresult()?
+            .into_iter()
+            .collect())
+    }
 }
 
 async fn connect(
     grpc_endpoint: String,
     database_name: String,
     create_db: bool,
-    max_message_size: Option<usize>,
+    config: ClientConfig,
 ) -> PyResult<Client> {
-    let mut client = Client::connect_with_max_message_size(
-        grpc_endpoint,
-        max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
-    )
-    .await
-    .into_pyresult()?;
+    let mut client = Client::connect_with_config(grpc_endpoint, config)
+        .await
+        .into_pyresult()?;
 
     if create_db {
         client
@@ -419,6 +507,16 @@ impl PyInsertOptions {
         self.options.max_num_rows_per_chunk = max_num_rows_per_chunk
     }
 
+    #[getter]
+    fn get_max_concurrent_inserts(&self) -> usize {
+        self.options.max_concurrent_inserts
+    }
+
+    #[setter]
+    fn set_max_concurrent_inserts(&mut self, max_concurrent_inserts: usize) {
+        self.options.max_concurrent_inserts = max_concurrent_inserts
+    }
+
     #[getter]
     fn get_create_schema(&self) -> bool {
         self.options.create_schema
@@ -438,6 +536,70 @@ impl PyInsertOptions {
     fn set_deduplicate_after_insert(&mut self, deduplicate_after_insert: bool) {
         self.options.deduplicate_after_insert = deduplicate_after_insert
     }
+
+    /// Run the insert without copying the data into the final tables. The number of
+    /// rows which would be inserted is logged instead.
+    #[getter]
+    fn get_dry_run(&self) -> bool {
+        self.options.dry_run
+    }
+
+    #[setter]
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.options.dry_run = dry_run
+    }
+
+    /// Validate a sample of the h3indexes of the dataframe before inserting. Enabled by default.
+    #[getter]
+    fn get_validate_h3indexes(&self) -> bool {
+        self.options.validate_h3indexes
+    }
+
+    #[setter]
+    fn set_validate_h3indexes(&mut self, validate_h3indexes: bool) {
+        self.options.validate_h3indexes = validate_h3indexes
+    }
+
+    /// Insert already compacted data without compacting it again.
+    #[getter]
+    fn get_skip_compaction(&self) -> bool {
+        self.options.skip_compaction
+    }
+
+    #[setter]
+    fn set_skip_compaction(&mut self, skip_compaction: bool) {
+        self.options.skip_compaction = skip_compaction
+    }
+
+    /// Callable to report the progress of the insert to. It gets called with the arguments
+    /// `(table_name, num_rows, elapsed_secs)` after each batch of rows has been written.
+    #[setter]
+    fn set_progress_callback(&mut self, callback: Option<PyObject>) {
+        self.options.observer = callback.map(|callback| {
+            Arc::new(PyCallbackInsertObserver { callback }) as Arc<dyn InsertObserver>
+        })
+    }
+}
+
+/// forwards the progress of inserts to a python callable
+#[derive(Debug)]
+struct PyCallbackInsertObserver {
+    callback: PyObject,
+}
+
+impl InsertObserver for PyCallbackInsertObserver {
+    fn on_batch_complete(&self, table_name: &str, num_rows: usize, elapsed: Duration) {
+        Python::with_gil(|py| {
+            if let Err(e) = self
+                .callback
+                .call1(py, (table_name, num_rows, elapsed.as_secs_f64()))
+            {
+                warn!("insert progress callback failed: {}", e);
+            }
+        })
+    }
+
+    fn on_complete(&self, _total_num_rows: usize, _total_elapsed: Duration) {}
 }
 
 #[pyclass]
@@ -460,4 +622,11 @@ impl PyTableSetQuery {
             query: TableSetQuery::TemplatedSelect(query_template),
         }
     }
+
+    /// Limit the number of rows fetched from each of the queried tables.
+    fn with_limit(&self, limit: u64) -> Self {
+        Self {
+            query: self.query.clone().with_limit(limit),
+        }
+    }
 }
@@ -32,8 +32,8 @@ impl PyCompactedTableSchema {
     }
 
     #[getter]
-    fn max_h3_resolution(&self) -> u8 {
-        self.schema.max_h3_resolution
+    fn max_h3_resolution(&self) -> PyResult<u8> {
+        self.schema.max_h3_resolution().into_pyresult()
     }
 
     fn to_json_string(&self) -> PyResult<String> {
@@ -50,6 +50,25 @@ impl PyCompactedTableSchema {
     fn sql_statements(&self) -> PyResult<Vec<String>> {
         self.schema.build_create_statements(&None).into_pyresult()
     }
+
+    fn alter_add_column_statements(&self, existing: &Self) -> PyResult<Vec<String>> {
+        self.schema
+            .alter_add_column_statements(&existing.schema)
+            .into_pyresult()
+    }
+
+    /// Statements to create materialized views aggregating the rows inserted into a base table
+    /// into the base table of the next coarser resolution.
+    ///
+    /// The views replace the aggregation done when inserting dataframes, so they must not be used
+    /// for tablesets written using `GRPCConnection.insert_h3dataframe_into_tableset`. Only columns using the
+    /// `sum` or `relativetocellarea` aggregations summed by the `SummingMergeTree` engine, `max` and `min`
+    /// columns and aggregate function columns are supported. `average` can not be merged from partial rows.
+    fn materialized_view_statements(&self) -> PyResult<Vec<String>> {
+        self.schema
+            .create_materialized_view_statements()
+            .into_pyresult()
+    }
 }
 
 #[pyclass]
@@ -91,10 +110,17 @@ pub struct PyCompactedTableSchemaBuilder {
     compression_method: Option<CompressionMethod>,
     h3_base_resolutions: Option<Vec<u8>>,
     use_compaction: bool,
+    base_suffix: bool,
     temporal_resolution: Option<TemporalResolution>,
     temporal_partitioning: Option<TemporalPartitioning>,
     h3_partitioning: Option<H3Partitioning>,
     partition_by: Option<Vec<String>>,
+    replication: Option<(String, String)>,
+    on_cluster: Option<String>,
+    description: Option<String>,
+    ttl: Option<(String, u32)>,
+    sample_by: Option<String>,
+    settings: Vec<(String, String)>,
     columns: Vec<(String, ColumnDefinition)>,
 }
 
@@ -108,10 +134,17 @@ impl PyCompactedTableSchemaBuilder {
             compression_method: None,
             h3_base_resolutions: None,
             use_compaction: true,
+            base_suffix: true,
             temporal_resolution: None,
             temporal_partitioning: None,
             h3_partitioning: None,
             partition_by: None,
+            replication: None,
+            on_cluster: None,
+            description: None,
+            ttl: None,
+            sample_by: None,
+            settings: vec![],
             columns: vec![],
         }
     }
@@ -123,7 +156,17 @@ impl PyCompactedTableSchemaBuilder {
         column_names: Option<Vec<String>>,
     ) -> PyResult<()> {
         self.table_engine = Some(match engine_name.to_lowercase().as_str() {
-            "replacingmergetree" => TableEngine::ReplacingMergeTree,
+            "replacingmergetree" => match column_names.as_deref() {
+                None | Some([]) => TableEngine::ReplacingMergeTree(None),
+                Some([version_column]) => {
+                    TableEngine::ReplacingMergeTree(Some(version_column.clone()))
+                }
+                Some(_) => {
+                    return Err(PyValueError::new_err(
+                        "ReplacingMergeTree accepts only a single version column",
+                    ))
+                }
+            },
             "aggregatingmergetree" => TableEngine::AggregatingMergeTree,
             "summingmergetree" => {
                 if let Some(sum_column_names) = column_names {
@@ -150,6 +193,11 @@ impl PyCompactedTableSchemaBuilder {
         self.use_compaction = use_compaction;
     }
 
+    /// Name the base tables without the `_base` suffix, for example `dataset_05` instead of `dataset_05_base`.
+    fn no_base_suffix(&mut self) {
+        self.base_suffix = false;
+    }
+
     fn h3_base_resolutions(&mut self, res: Vec<u8>) {
         self.h3_base_resolutions = Some(res)
     }
@@ -162,14 +210,7 @@ impl PyCompactedTableSchemaBuilder {
         kwargs: Option<&PyDict>,
     ) -> PyResult<()> {
         let column_kwargs = ColumnKwargs::extract(kwargs)?;
-        let sc = SimpleColumn::new(
-            datatype_from_string(datatype_str)?,
-            column_kwargs.order_key_position,
-            column_kwargs
-                .compression_method
-                .map(|pcm| pcm.compression_method.clone()),
-            column_kwargs.nullable,
-        );
+        let sc = column_kwargs.simple_column(datatype_from_string(datatype_str)?);
         self.columns
             .push((column_name, ColumnDefinition::Simple(sc)));
         Ok(())
@@ -192,14 +233,7 @@ impl PyCompactedTableSchemaBuilder {
         kwargs: Option<&PyDict>,
     ) -> PyResult<()> {
         let column_kwargs = ColumnKwargs::extract(kwargs)?;
-        let sc = SimpleColumn::new(
-            datatype_from_string(datatype_str)?,
-            column_kwargs.order_key_position,
-            column_kwargs
-                .compression_method
-                .map(|pcm| pcm.compression_method.clone()),
-            column_kwargs.nullable,
-        );
+        let sc = column_kwargs.simple_column(datatype_from_string(datatype_str)?);
         let agg = match agg_method_str.to_lowercase().as_str() {
             "sum" => AggregationMethod::Sum,
             "min" => AggregationMethod::Min,
@@ -219,6 +253,25 @@ impl PyCompactedTableSchemaBuilder {
         Ok(())
     }
 
+    /// Add a column storing the state of an aggregate function, like `AggregateFunction(avg, Float32)`.
+    ///
+    /// Tables using the `AggregatingMergeTree` engine require at least one of these columns.
+    fn add_aggregate_function_column(
+        &mut self,
+        column_name: String,
+        function: String,
+        datatype_str: String,
+    ) -> PyResult<()> {
+        self.columns.push((
+            column_name,
+            ColumnDefinition::AggregateFunction {
+                function,
+                inner_type: datatype_from_string(datatype_str)?,
+            },
+        ));
+        Ok(())
+    }
+
     fn temporal_resolution(&mut self, name: String) -> PyResult<()> {
         self.temporal_resolution = Some(match name.to_lowercase().as_str() {
             "second" | "seconds" => TemporalResolution::Second,
@@ -243,6 +296,14 @@ impl PyCompactedTableSchemaBuilder {
             .map(|s| s.as_str().to_lowercase())
             .unwrap_or_else(|| "".to_string());
         self.temporal_partitioning = Some(match unit_string.as_str() {
+            "day" | "days" => {
+                if cap.get(2).map(|s| s.as_str() != "1").unwrap_or(false) {
+                    return Err(PyValueError::new_err(
+                        "Only single days are supported in temporal partitioning",
+                    ));
+                }
+                TemporalPartitioning::Day
+            }
             "month" | "months" => {
                 let num_months: u8 = cap
                     .get(2)
@@ -308,6 +369,41 @@ impl PyCompactedTableSchemaBuilder {
         self.partition_by = Some(column_names)
     }
 
+    /// Use the `Replicated*MergeTree` variant of the table engine.
+    ///
+    /// The table name gets appended to the `zookeeper_path` when it does not contain the `{table}` macro.
+    fn with_replication(&mut self, zookeeper_path: String, replica_name: String) {
+        self.replication = Some((zookeeper_path, replica_name))
+    }
+
+    /// Run the `CREATE` and `DROP` statements of the tables `ON CLUSTER`.
+    fn on_cluster(&mut self, cluster_name: String) {
+        self.on_cluster = Some(cluster_name)
+    }
+
+    /// Description of the tableset. Gets stored as the comment of the tables.
+    fn description(&mut self, description: String) {
+        self.description = Some(description)
+    }
+
+    /// Delete rows once the value of the temporal column `column_name` is older than `interval_days` days.
+    fn ttl(&mut self, column_name: String, interval_days: u32) {
+        self.ttl = Some((column_name, interval_days))
+    }
+
+    /// Sampling expression of the tables used by `SAMPLE` queries. ClickHouse requires the
+    /// expression to be part of the sorting key, for example the `h3index` column.
+    fn sample_by(&mut self, expression: String) {
+        self.sample_by = Some(expression)
+    }
+
+    /// Set a table-level setting of the `MergeTree` engine, for example `setting("index_granularity", "4096")`.
+    ///
+    /// The value is used verbatim in the `SETTINGS` clause, so string values must be quoted.
+    fn setting(&mut self, key: String, value: String) {
+        self.settings.push((key, value))
+    }
+
     fn build(&self) -> PyResult<PyCompactedTableSchema> {
         let mut builder = CompactedTableSchemaBuilder::new(&self.table_name);
 
@@ -318,6 +414,9 @@ impl PyCompactedTableSchemaBuilder {
             builder = builder.compression_method(cm.clone())
         }
         builder = builder.use_compacted_resolutions(self.use_compaction);
+        if !self.base_suffix {
+            builder = builder.no_base_suffix();
+        }
         if let Some(h3res) = &self.h3_base_resolutions {
             builder = builder.h3_base_resolutions(h3res.clone())
         }
@@ -330,12 +429,30 @@ impl PyCompactedTableSchemaBuilder {
         if let Some(hp) = &self.h3_partitioning {
             builder = builder.h3_partitioning(hp.clone());
         }
-        if let Some(pb) = &self.partition_by {
-            builder = builder.partition_by(pb.clone())
+        if let Some((zookeeper_path, replica_name)) = &self.replication {
+            builder = builder.with_replication(zookeeper_path, replica_name)
+        }
+        if let Some(cluster_name) = &self.on_cluster {
+            builder = builder.on_cluster(cluster_name)
+        }
+        if let Some(description) = &self.description {
+            builder = builder.description(description)
+        }
+        if let Some((column_name, interval_days)) = &self.ttl {
+            builder = builder.ttl(column_name, *interval_days)
+        }
+        if let Some(expression) = &self.sample_by {
+            builder = builder.sample_by(expression)
+        }
+        for (key, value) in self.settings.iter() {
+            builder = builder.setting(key, value.as_str())
         }
         for (col_name, col_def) in self.columns.iter() {
             builder = builder.add_column(col_name.as_str(), col_def.clone())
         }
+        if let Some(pb) = &self.partition_by {
+            builder = builder.try_partition_by(pb.clone()).into_pyresult()?;
+        }
 
         let inner_schema = builder.build().into_pyresult()?;
         Ok(PyCompactedTableSchema {
@@ -355,6 +472,7 @@ struct ColumnKwargs<'a> {
     order_key_position: Option<u8>,
     compression_method: Option<PyRef<'a, PyCompressionMethod>>,
     nullable: bool,
+    comment: Option<String>,
 }
 
 impl<'a> ColumnKwargs<'a> {
@@ -365,7 +483,22 @@ impl<'a> ColumnKwargs<'a> {
             kwargs.order_key_position = extract_dict_item_option(dict, "order_key_position")?;
             kwargs.compression_method = extract_dict_item_option(dict, "compression_method")?;
             kwargs.nullable = nullable.unwrap_or(false);
+            kwargs.comment = extract_dict_item_option(dict, "comment")?;
         }
         Ok(kwargs)
     }
+
+    fn simple_column(self, datatype: ClickhouseDataType) -> SimpleColumn {
+        let sc = SimpleColumn::new(
+            datatype,
+            self.order_key_position,
+            self.compression_method
+                .map(|pcm| pcm.compression_method.clone()),
+            self.nullable,
+        );
+        match &self.comment {
+            Some(comment) => sc.with_comment(comment),
+            None => sc,
+        }
+    }
 }
@@ -28,6 +28,17 @@ impl PyTableSet {
     pub fn base_resolutions(&self) -> Vec<u8> {
         self.tableset.base_resolutions()
     }
+
+    /// The finest resolution data can be queried at. `None` when the tableset has no base tables.
+    #[getter]
+    pub fn max_queryable_resolution(&self) -> Option<u8> {
+        self.tableset.max_queryable_resolution()
+    }
+
+    /// Check if data at the given resolution can be queried from the tableset.
+    pub fn covers_resolution(&self, h3_resolution: u8) -> bool {
+        self.tableset.covers_resolution(h3_resolution)
+    }
 }
 
 impl From<TableSet> for PyTableSet {
@@ -11,9 +11,9 @@ use tokio::sync::Mutex;
 use tokio::time::timeout;
 use tracing::debug;
 use ukis_h3cellstore::clickhouse::compacted_tables::traversal::{
-    traverse, TraversalArea, TraversalOptions, Traverser,
+    traverse, TraversalArea, TraversalOptions, TraversalSortStrategy, Traverser,
 };
-use ukis_h3cellstore::clickhouse::compacted_tables::TableSetQuery;
+use ukis_h3cellstore::clickhouse::compacted_tables::{QueryPriority, TableSetQuery};
 
 use crate::clickhouse::grpc::{GRPCConnection, PyTableSetQuery};
 use crate::error::IntoPyResult;
@@ -48,6 +48,17 @@ pub struct PyTraversalOptions {
 
     /// uncompact the cells loaded from the db. This should be true in most cases.
     do_uncompact: bool,
+
+    /// include the cells with in the buffer around the traversal-cell into the returned data.
+    ///
+    /// Unit for the buffer is number of cells in reslution `h3_resolution`.
+    include_buffer: u32,
+
+    /// the order in which the traversal cells are visited.
+    sort_strategy: TraversalSortStrategy,
+
+    /// priority of the queries on the ClickHouse server.
+    priority: QueryPriority,
 }
 
 impl Default for PyTraversalOptions {
@@ -58,6 +69,9 @@ impl Default for PyTraversalOptions {
             num_connections: upstream_defaults.num_connections,
             filter_query: upstream_defaults.filter_query,
             do_uncompact: upstream_defaults.do_uncompact,
+            include_buffer: upstream_defaults.include_buffer,
+            sort_strategy: upstream_defaults.sort_strategy,
+            priority: upstream_defaults.priority,
         }
     }
 }
@@ -77,6 +91,37 @@ impl PyTraversalOptions {
             {
                 kwargs.filter_query = Some(fq.query.clone());
             }
+            if let Some(ib) = extract_dict_item_option(dict, "include_buffer")? {
+                kwargs.include_buffer = ib;
+            }
+            if let Some(ss) = extract_dict_item_option::<String, _>(dict, "sort_strategy")? {
+                kwargs.sort_strategy = match ss.to_lowercase().as_str() {
+                    "h3index" => TraversalSortStrategy::H3Index,
+                    "hilbert" | "hilbertcurve" => TraversalSortStrategy::HilbertCurve,
+                    "shuffled" => TraversalSortStrategy::Shuffled(
+                        extract_dict_item_option::<u64, _>(dict, "shuffle_seed")?.unwrap_or(0),
+                    ),
+                    _ => {
+                        return Err(PyValueError::new_err(format!(
+                            "unsupported sort_strategy: {}",
+                            ss
+                        )))
+                    }
+                };
+            }
+            if let Some(p) = extract_dict_item_option::<String, _>(dict, "priority")? {
+                kwargs.priority = match p.to_lowercase().as_str() {
+                    "high" => QueryPriority::High,
+                    "normal" => QueryPriority::Normal,
+                    "low" => QueryPriority::Low,
+                    _ => {
+                        return Err(PyValueError::new_err(format!(
+                            "unsupported priority: {}",
+                            p
+                        )))
+                    }
+                };
+            }
         }
         Ok(kwargs)
     }
@@ -111,6 +156,28 @@ impl PyTraverser {
         })
     }
 
+    /// Number of traversal cells traversed so far, including the cells which
+    /// did not contain any data.
+    #[getter]
+    fn num_traversed_cells(&self) -> usize {
+        let trav = self.traverser.clone();
+        self.runtime.block_on(async {
+            let guard = trav.lock().await;
+            guard.num_traversed_cells()
+        })
+    }
+
+    /// Fraction of the traversal cells traversed so far in the range `0.0` to `1.0`.
+    /// Useful to drive a progress bar.
+    #[getter]
+    fn fraction_complete(&self) -> f64 {
+        let trav = self.traverser.clone();
+        self.runtime.block_on(async {
+            let guard = trav.lock().await;
+            guard.fraction_complete()
+        })
+    }
+
     /// The H3 resolution used for the traversal process
     #[getter]
     fn traversal_h3_resolution(&self) -> u8 {
@@ -171,6 +238,9 @@ impl PyTraverser {
             num_connections: options.num_connections,
             filter_query: options.filter_query,
             do_uncompact: options.do_uncompact,
+            include_buffer: options.include_buffer,
+            sort_strategy: options.sort_strategy,
+            priority: options.priority,
             ..Default::default()
         };
 
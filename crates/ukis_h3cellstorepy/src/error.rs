@@ -97,6 +97,7 @@ impl ToCustomPyErr for ukis_h3cellstore::Error {
 
             Self::MissingPrecondidtionsForPartialOptimization
             | Self::TableSetNotFound(_)
+            | Self::TableSetAlreadyExists(_)
             | Self::DatabaseNotFound(_)
             | Self::Io(_)
             | Self::TonicTansport(_)
@@ -111,12 +112,17 @@ impl ToCustomPyErr for ukis_h3cellstore::Error {
             | Self::DataframeInvalidH3IndexType(_, _)
             | Self::DataframeMissingColumn(_)
             | Self::UnsupportedH3Resolution(_)
+            | Self::H3ResolutionNotInTableSet(_, _)
+            | Self::UnknownDatatype(_)
+            | Self::IncompatibleDatatype(_, _, _)
             | Self::MixedH3Resolutions
             | Self::EmptyCells
             | Self::MissingQueryPlaceholder(_)
+            | Self::InvalidQueryLimit(_)
             | Self::SchemaValidationError(_, _)
             | Self::NoH3ResolutionsDefined
-            | Self::MissingIndexValue => PyValueError::new_err(self.to_string()),
+            | Self::MissingIndexValue
+            | Self::InvalidH3Index(_) => PyValueError::new_err(self.to_string()),
 
             Self::Abort => PyKeyboardInterrupt::new_err(self.to_string()),
         }
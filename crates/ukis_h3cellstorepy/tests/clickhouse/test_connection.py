@@ -22,6 +22,12 @@ def test_connection_execute_error_propagation(clickhouse_grpc_endpoint):
     assert "'something_invalid'" in str(excinfo)
 
 
+def test_connection_execute_empty_query(clickhouse_grpc_endpoint):
+    con = GRPCConnection(clickhouse_grpc_endpoint, "default")
+    with pytest.raises(ValueError):
+        con.execute("  ")
+
+
 def test_connection_execute_into_dataframe_polars(clickhouse_grpc_endpoint, pl):
     con = GRPCConnection(clickhouse_grpc_endpoint, "system")
     df = con.execute_into_dataframe("select name from databases").to_polars()